@@ -0,0 +1,220 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+/// Length of one level measurement
+const FRAME_MS: u32 = 10;
+/// Frames quieter than this are treated as silence and leave the gain alone,
+/// so pauses aren't pumped up to speech level
+const GATE_DB: f32 = -55.0;
+/// The AGC may turn a loud microphone down by at most this much
+const MIN_GAIN_DB: f32 = -20.0;
+/// Level above which the limiter starts to bend the signal
+const LIMITER_KNEE: f32 = 0.8;
+
+/// Automatic gain control settings for one input device
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AgcSettings {
+    pub enabled: bool,
+    /// Loudness speech is brought to, in dBFS RMS
+    pub target_db: f32,
+    /// How quickly the gain drops when the input gets louder
+    pub attack_ms: u32,
+    /// How quickly the gain recovers when the input gets quieter
+    pub release_ms: u32,
+    /// Most the AGC will boost a quiet microphone, in dB
+    pub max_gain_db: f32,
+}
+
+impl Default for AgcSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            target_db: -20.0,
+            attack_ms: 10,
+            release_ms: 500,
+            max_gain_db: 20.0,
+        }
+    }
+}
+
+impl AgcSettings {
+    pub fn validate(&self) -> Result<()> {
+        if !(-40.0..=-6.0).contains(&self.target_db) {
+            return Err(anyhow!("AGC target must be between -40 and -6 dBFS"));
+        }
+        if !(1..=1000).contains(&self.attack_ms) {
+            return Err(anyhow!("AGC attack must be between 1 and 1000 ms"));
+        }
+        if !(10..=5000).contains(&self.release_ms) {
+            return Err(anyhow!("AGC release must be between 10 and 5000 ms"));
+        }
+        if !(0.0..=40.0).contains(&self.max_gain_db) {
+            return Err(anyhow!("AGC maximum gain must be between 0 and 40 dB"));
+        }
+        Ok(())
+    }
+}
+
+/// Automatic gain control followed by a soft limiter.
+///
+/// The level of each 10 ms frame is compared with the target and the gain
+/// moves towards the difference, quickly when it has to come down and slowly
+/// when it can go up. The gain is ramped across each frame so it never steps.
+/// Whatever still ends up above the knee is bent smoothly towards full scale
+/// instead of clipping.
+pub struct AutomaticGainControl {
+    settings: AgcSettings,
+    frame_len: usize,
+    /// Current gain in dB
+    gain_db: f32,
+}
+
+impl AutomaticGainControl {
+    pub fn new(settings: AgcSettings, sample_rate: u32) -> Self {
+        Self {
+            settings,
+            frame_len: (sample_rate * FRAME_MS / 1000).max(1) as usize,
+            gain_db: 0.0,
+        }
+    }
+
+    pub fn set_settings(&mut self, settings: AgcSettings) {
+        self.settings = settings;
+    }
+
+    /// Starts over from unity gain
+    pub fn reset(&mut self) {
+        self.gain_db = 0.0;
+    }
+
+    /// Applies the gain and limiter to a block of mono samples in place
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for frame in samples.chunks_mut(self.frame_len) {
+            let rms = (frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32).sqrt();
+            let level_db = 20.0 * rms.max(1e-9).log10();
+
+            let start = db_to_gain(self.gain_db);
+            if level_db > GATE_DB {
+                let wanted = (self.settings.target_db - level_db).clamp(MIN_GAIN_DB, self.settings.max_gain_db);
+                let time_ms = if wanted < self.gain_db {
+                    self.settings.attack_ms
+                } else {
+                    self.settings.release_ms
+                };
+                let alpha = 1.0 - (-(FRAME_MS as f32) / time_ms as f32).exp();
+                self.gain_db += alpha * (wanted - self.gain_db);
+            }
+            // A lowered maximum takes effect right away
+            self.gain_db = self.gain_db.min(self.settings.max_gain_db);
+            let end = db_to_gain(self.gain_db);
+
+            let step = (end - start) / frame.len() as f32;
+            for (i, sample) in frame.iter_mut().enumerate() {
+                *sample = soft_limit(*sample * (start + step * (i + 1) as f32));
+            }
+        }
+    }
+}
+
+fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+/// Leaves samples under the knee alone and bends louder ones towards ±1
+fn soft_limit(sample: f32) -> f32 {
+    let magnitude = sample.abs();
+    if magnitude <= LIMITER_KNEE {
+        return sample;
+    }
+    let headroom = 1.0 - LIMITER_KNEE;
+    let limited = LIMITER_KNEE + headroom * ((magnitude - LIMITER_KNEE) / headroom).tanh();
+    limited.copysign(sample)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48000;
+    const BLOCK: usize = 480;
+
+    fn enabled() -> AgcSettings {
+        AgcSettings {
+            enabled: true,
+            ..Default::default()
+        }
+    }
+
+    /// A 10 ms block with the given RMS
+    fn block(rms: f32) -> Vec<f32> {
+        (0..BLOCK).map(|i| if i % 2 == 0 { rms } else { -rms }).collect()
+    }
+
+    fn level_db(samples: &[f32]) -> f32 {
+        let rms = (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt();
+        20.0 * rms.log10()
+    }
+
+    #[test]
+    fn quiet_input_converges_to_target() {
+        let settings = enabled();
+        let mut agc = AutomaticGainControl::new(settings, RATE);
+
+        // -34 dBFS, so 14 dB short of the target
+        let mut levels = Vec::new();
+        for _ in 0..500 {
+            let mut samples = block(0.02);
+            agc.process(&mut samples);
+            levels.push(level_db(&samples));
+        }
+
+        // Rising steadily at the release rate rather than jumping
+        assert!(levels[0] < -33.0);
+        assert!(levels.windows(2).all(|pair| pair[1] >= pair[0] - 1e-3));
+        let last = levels[levels.len() - 1];
+        assert!((last - settings.target_db).abs() < 0.5, "settled at {:.1} dBFS", last);
+    }
+
+    #[test]
+    fn gain_stays_within_maximum() {
+        let settings = AgcSettings {
+            max_gain_db: 6.0,
+            ..enabled()
+        };
+        let mut agc = AutomaticGainControl::new(settings, RATE);
+
+        let mut samples = Vec::new();
+        for _ in 0..500 {
+            samples = block(0.01);
+            agc.process(&mut samples);
+        }
+        // -40 dBFS can only be brought up to -34
+        assert!((level_db(&samples) + 34.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn limiter_keeps_output_within_full_scale() {
+        let mut agc = AutomaticGainControl::new(enabled(), RATE);
+        // Let the gain climb on a quiet stretch, then shout into it
+        for _ in 0..300 {
+            agc.process(&mut block(0.005));
+        }
+        let mut peak = 0.0f32;
+        for _ in 0..10 {
+            let mut samples = block(0.9);
+            agc.process(&mut samples);
+            peak = samples.iter().fold(peak, |peak, s| peak.max(s.abs()));
+        }
+        // The boosted shout reached the limiter, which held it under full scale
+        assert!(peak > LIMITER_KNEE && peak <= 1.0, "peak {}", peak);
+
+        assert_eq!(soft_limit(0.5), 0.5);
+        assert_eq!(soft_limit(-0.8), -0.8);
+        for sample in [0.9, 1.5, 10.0, 1000.0] {
+            let limited = soft_limit(sample);
+            assert!(limited > LIMITER_KNEE && limited <= 1.0);
+            assert_eq!(soft_limit(-sample), -limited);
+        }
+    }
+}
@@ -1,6 +1,8 @@
+use crate::tts::SynthesizedAudio;
 use anyhow::{anyhow, Result};
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use cpal::{Device, Host, SampleFormat, Stream, StreamConfig};
+use rubato::{Resampler, SincFixedIn, SincInterpolationType, SincInterpolationParameters, WindowFunction};
 use std::collections::VecDeque;
 use std::sync::{Arc, Mutex};
 
@@ -72,6 +74,11 @@ impl AudioManager {
             .ok_or_else(|| anyhow!("Output device not found: {}", name))
     }
 
+    /// Name of the input device in use, which may be the system default
+    pub fn get_input_device_name(&self) -> Result<String> {
+        Ok(self.get_input_device()?.name()?)
+    }
+
     pub fn get_input_device(&self) -> Result<Device> {
         match &self.input_device_name {
             Some(name) => self.get_input_device_by_name(name),
@@ -113,6 +120,36 @@ impl Default for AudioManager {
     }
 }
 
+/// Resamples audio from one sample rate to another
+pub fn resample_audio(input: &[f32], from_rate: u32, to_rate: u32) -> Result<Vec<f32>> {
+    if from_rate == to_rate {
+        return Ok(input.to_vec());
+    }
+
+    let params = SincInterpolationParameters {
+        sinc_len: 256,
+        f_cutoff: 0.95,
+        interpolation: SincInterpolationType::Linear,
+        oversampling_factor: 256,
+        window: WindowFunction::BlackmanHarris2,
+    };
+
+    let ratio = to_rate as f64 / from_rate as f64;
+    let mut resampler = SincFixedIn::<f32>::new(
+        ratio,
+        2.0, // max relative ratio
+        params,
+        input.len(),
+        1, // mono
+    )?;
+
+    let mut waves_in = vec![input.to_vec()];
+    let waves_out = resampler.process(&waves_in, None);
+    scrub(&mut waves_in[0]);
+
+    Ok(waves_out?.into_iter().next().unwrap_or_default())
+}
+
 /// Creates an input stream that sends audio data to the provided callback.
 /// Returns the stream which must be kept alive for audio to flow.
 pub fn create_input_stream<F>(
@@ -161,12 +198,100 @@ where
     Ok(stream)
 }
 
-/// Creates an output stream that pulls audio from a shared buffer.
+/// Mono samples waiting for the output device. Only synthesized speech and
+/// silence can be queued, so there is no way for microphone audio to reach
+/// the speakers.
+#[derive(Default)]
+pub struct OutputBuffer {
+    samples: Mutex<VecDeque<f32>>,
+}
+
+impl OutputBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues synthesized speech. Captured samples don't type-check:
+    ///
+    /// ```compile_fail,E0308
+    /// let buffer = parrot_lib::OutputBuffer::new();
+    /// let captured: Vec<f32> = vec![0.5; 480];
+    /// buffer.push(captured);
+    /// ```
+    pub fn push(&self, audio: SynthesizedAudio) {
+        self.samples.lock().unwrap().extend(audio.into_samples());
+    }
+
+    /// Queues `len` samples of silence
+    ///
+    /// ```
+    /// let buffer = parrot_lib::OutputBuffer::new();
+    /// buffer.push_silence(480);
+    /// assert_eq!(buffer.len(), 480);
+    /// ```
+    pub fn push_silence(&self, len: usize) {
+        let mut samples = self.samples.lock().unwrap();
+        let total = samples.len() + len;
+        samples.resize(total, 0.0);
+    }
+
+    /// Samples still waiting to be played
+    pub fn len(&self) -> usize {
+        self.samples.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Fills interleaved output frames, duplicating each mono sample to every
+    /// channel and padding with silence once the buffer runs dry
+    fn fill(&self, data: &mut [f32], channels: usize) {
+        let mut samples = self.samples.lock().unwrap();
+        for frame in data.chunks_mut(channels) {
+            frame.fill(samples.pop_front().unwrap_or(0.0));
+        }
+    }
+}
+
+/// Overwrites captured audio, including spare capacity still holding old
+/// samples, so it doesn't linger in memory once it has been used
+pub fn scrub<T: Copy + Default>(samples: &mut Vec<T>) {
+    samples.fill(T::default());
+    for sample in samples.spare_capacity_mut() {
+        sample.write(T::default());
+    }
+    // Keep the writes from being optimized away as dead stores
+    std::hint::black_box(samples);
+}
+
+/// Appends to a buffer of captured audio. When it has to grow, the old
+/// allocation is wiped rather than left behind by the reallocation.
+pub fn extend_scrubbed(buffer: &mut Vec<f32>, samples: &[f32]) {
+    if buffer.capacity() - buffer.len() < samples.len() {
+        let mut grown = Vec::with_capacity((buffer.len() + samples.len()).max(buffer.capacity() * 2));
+        grown.extend_from_slice(buffer);
+        scrub(buffer);
+        *buffer = grown;
+    }
+    buffer.extend_from_slice(samples);
+}
+
+/// Overwrites and empties a ring buffer of captured audio
+pub fn scrub_ring(samples: &mut VecDeque<f32>) {
+    // Growing it to its capacity writes every slot, used or not
+    samples.resize(samples.capacity(), 0.0);
+    samples.iter_mut().for_each(|s| *s = 0.0);
+    samples.clear();
+    std::hint::black_box(samples);
+}
+
+/// Creates an output stream that plays from an output buffer.
 /// The buffer contains mono samples which are duplicated to all output channels.
 pub fn create_output_stream(
     device: &Device,
     config: &StreamConfig,
-    audio_buffer: Arc<Mutex<VecDeque<f32>>>,
+    audio_buffer: Arc<OutputBuffer>,
 ) -> Result<Stream> {
     let channels = config.channels as usize;
     log::info!("Creating output stream with {} channels", channels);
@@ -174,15 +299,7 @@ pub fn create_output_stream(
     let stream = device.build_output_stream(
         config,
         move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-            let mut buffer = audio_buffer.lock().unwrap();
-            // Process frame by frame (each frame has `channels` samples)
-            for frame in data.chunks_mut(channels) {
-                // Get one mono sample and duplicate to all channels
-                let sample = buffer.pop_front().unwrap_or(0.0);
-                for channel_sample in frame.iter_mut() {
-                    *channel_sample = sample;
-                }
-            }
+            audio_buffer.fill(data, channels);
         },
         |err| log::error!("Audio output error: {}", err),
         None,
@@ -191,3 +308,116 @@ pub fn create_output_stream(
     stream.play()?;
     Ok(stream)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command_stt::{CommandSttConfig, SttOutput};
+    use crate::command_tts::{CommandOutput, CommandVoiceConfig};
+    use crate::disguise::DisguiseSettings;
+    use crate::pipeline::PipelineState;
+    use crate::stages::{spawn_stages, SttJob};
+    use crate::stt::SttBackendKind;
+    use std::collections::HashSet;
+    use std::sync::atomic::AtomicBool;
+    use std::time::Instant;
+
+    /// Stands in for microphone audio. Every value is above anything the
+    /// synthesized audio in these tests can reach, so any match is a leak.
+    fn microphone(len: usize) -> Vec<f32> {
+        (0..len).map(|i| 0.5 + i as f32 * 1e-5).collect()
+    }
+
+    fn contains_any(output: &[f32], input: &[f32]) -> bool {
+        let input: HashSet<u32> = input.iter().map(|s| s.to_bits()).collect();
+        output.iter().any(|s| *s != 0.0 && input.contains(&s.to_bits()))
+    }
+
+    /// Sends captured audio through every stage, with commands standing in for
+    /// the recognizer and the voice, and plays out what reaches the buffer
+    #[cfg(unix)]
+    #[test]
+    fn output_plays_only_synthesized_audio() {
+        let state = Arc::new(PipelineState::new().unwrap());
+        {
+            let mut stt = state.stt.lock().unwrap();
+            stt.set_command(CommandSttConfig {
+                program: "sh".to_string(),
+                args: vec!["-c".to_string(), "cat > /dev/null; echo Hello there.".to_string()],
+                output: SttOutput::Text,
+            })
+            .unwrap();
+            stt.set_active(SttBackendKind::Command).unwrap();
+        }
+        {
+            // Raw PCM of a constant level well below the microphone's
+            let mut tts = state.tts.lock().unwrap();
+            tts.add_command_voice(CommandVoiceConfig {
+                id: "test".to_string(),
+                name: "Test".to_string(),
+                program: "sh".to_string(),
+                args: ["-c", "yes | head -c 8820", "sh", "{text}"].map(String::from).to_vec(),
+                output: CommandOutput::Raw,
+                sample_rate: 22050,
+                language: None,
+                num_speakers: 1,
+            })
+            .unwrap();
+            tts.select_voice("test", None).unwrap();
+        }
+        state
+            .set_disguise(DisguiseSettings {
+                pitch_semitones: 3.0,
+                ..Default::default()
+            })
+            .unwrap();
+
+        let app = tauri::test::mock_app();
+        let buffer = Arc::new(OutputBuffer::new());
+        let stages = spawn_stages(
+            Arc::clone(&state),
+            app.handle().clone(),
+            Arc::new(AtomicBool::new(false)),
+            48000,
+            48000,
+            Arc::clone(&buffer),
+        );
+        let input = microphone(16000);
+        for _ in 0..2 {
+            let job = SttJob::Final {
+                audio: input.clone(),
+                heard_at: Instant::now(),
+            };
+            stages.stt_sender.send(job).unwrap();
+        }
+        // Waits for both utterances to be spoken
+        stages.shutdown();
+
+        assert!(!buffer.is_empty(), "nothing was synthesized");
+        let queued = buffer.len();
+
+        // Play everything queued and then some, in stereo
+        let mut output = vec![f32::NAN; (queued + 1000) * 2];
+        for block in output.chunks_mut(512) {
+            buffer.fill(block, 2);
+        }
+
+        assert!(output.chunks(2).all(|frame| frame[0] == frame[1]));
+        assert!(output[queued * 2..].iter().all(|s| *s == 0.0));
+        assert!(!contains_any(&output, &input));
+        assert_eq!(buffer.len(), 0);
+    }
+
+    #[test]
+    fn scrub_wipes_audio_and_spare_capacity() {
+        let mut audio = microphone(1000);
+        // What's cut off stays in the allocation until overwritten
+        audio.truncate(100);
+        scrub(&mut audio);
+
+        assert_eq!(audio.len(), 100);
+        assert!(audio.iter().all(|s| *s == 0.0));
+        // SAFETY: scrub wrote every slot of the spare capacity
+        assert!(audio.spare_capacity_mut().iter().all(|s| unsafe { s.assume_init() } == 0.0));
+    }
+}
@@ -0,0 +1,182 @@
+use crate::audio::{create_input_stream, extend_scrubbed, scrub, AudioManager};
+use crate::vad::VadSettings;
+use anyhow::{anyhow, Result};
+use cpal::traits::DeviceTrait;
+use cpal::{Device, SampleFormat, StreamConfig};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter};
+
+/// How long to record the room with nobody talking
+const NOISE_MS: u64 = 3000;
+/// How long to record the user talking
+const SPEECH_MS: u64 = 4000;
+/// Length of one analysis frame
+const FRAME_MS: u32 = 10;
+/// Level the gain aims to bring speech to, about -20 dBFS
+const TARGET_SPEECH_RMS: f32 = 0.1;
+/// Loudest peak allowed after gain, so calibration never makes the input clip
+const MAX_PEAK: f32 = 0.9;
+const GAIN_RANGE: (f32, f32) = (0.25, 8.0);
+/// Bounds for the VAD open threshold derived from a calibration, in dB
+const OPEN_DB_RANGE: (f32, f32) = (3.0, 40.0);
+/// Speech has to be at least this much louder than the room to calibrate from
+const MIN_SPEECH_MARGIN_DB: f32 = 6.0;
+
+/// Result of a calibration run. Levels are RMS after the recommended gain,
+/// which is how the VAD and the `audio-level` event see them.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Calibration {
+    pub noise_floor: f32,
+    /// Level halfway (in dB) between the room and the user's voice
+    pub threshold: f32,
+    pub input_gain: f32,
+}
+
+impl Calibration {
+    /// How far above the noise floor the threshold sits, in dB
+    pub fn threshold_db(&self) -> f32 {
+        20.0 * (self.threshold / self.noise_floor.max(f32::EPSILON)).log10()
+    }
+
+    /// Moves the VAD thresholds to the calibrated level. The detector keeps
+    /// tracking the room, so the threshold is stored relative to the floor.
+    pub fn apply_to(&self, vad: &mut VadSettings) {
+        let (min, max) = OPEN_DB_RANGE;
+        vad.open_db = self.threshold_db().clamp(min, max);
+        vad.close_db = vad.open_db / 2.0;
+        if self.threshold > 0.0 {
+            vad.min_threshold = vad.min_threshold.min(self.threshold);
+        }
+    }
+}
+
+/// Checks that an input gain is within the range calibration can recommend
+pub fn validate_gain(gain: f32) -> Result<()> {
+    let (min, max) = GAIN_RANGE;
+    if !(min..=max).contains(&gain) {
+        return Err(anyhow!("Input gain must be between {} and {}", min, max));
+    }
+    Ok(())
+}
+
+/// Records the room and then the user speaking on the selected input device
+/// and works out the calibration from them. Each phase is announced with a
+/// "calibration-status" event ("noise", then "speech").
+pub fn calibrate(audio_manager: &Mutex<AudioManager>, app: &AppHandle) -> Result<Calibration> {
+    // Only hold the manager long enough to look up the device
+    let (device, config, sample_format) = {
+        let manager = audio_manager.lock().unwrap();
+        let device = manager.get_input_device()?;
+        let (config, format) = manager.get_input_config()?;
+        (device, config, format)
+    };
+    log::info!("Calibrating input device: {:?}", device.name());
+
+    let _ = app.emit("calibration-status", "noise");
+    let mut noise = record_input(&device, &config, sample_format, NOISE_MS)?;
+    let _ = app.emit("calibration-status", "speech");
+    let mut speech = match record_input(&device, &config, sample_format, SPEECH_MS) {
+        Ok(speech) => speech,
+        Err(e) => {
+            scrub(&mut noise);
+            return Err(e);
+        }
+    };
+
+    // The recordings are only needed for the levels
+    let calibration = analyze(&noise, &speech, config.sample_rate.0);
+    scrub(&mut noise);
+    scrub(&mut speech);
+    let calibration = calibration?;
+    log::info!(
+        "Calibrated: noise floor {:.4}, threshold {:.4}, gain {:.2}",
+        calibration.noise_floor,
+        calibration.threshold,
+        calibration.input_gain
+    );
+    Ok(calibration)
+}
+
+/// Records `duration_ms` of mono audio
+fn record_input(
+    device: &Device,
+    config: &StreamConfig,
+    sample_format: SampleFormat,
+    duration_ms: u64,
+) -> Result<Vec<f32>> {
+    let channels = config.channels as usize;
+    let expected = (config.sample_rate.0 as u64 * duration_ms / 1000) as usize;
+    let recorded: Arc<Mutex<Vec<f32>>> = Arc::new(Mutex::new(Vec::with_capacity(expected)));
+    let recorded_clone = Arc::clone(&recorded);
+
+    let stream = create_input_stream(device, config, sample_format, move |mut data: Vec<f32>| {
+        let mut mono: Vec<f32> = data
+            .chunks(channels)
+            .map(|chunk| chunk.iter().sum::<f32>() / channels as f32)
+            .collect();
+        extend_scrubbed(&mut recorded_clone.lock().unwrap(), &mono);
+        scrub(&mut mono);
+        scrub(&mut data);
+    })?;
+    thread::sleep(Duration::from_millis(duration_ms));
+    drop(stream);
+
+    let audio = std::mem::take(&mut *recorded.lock().unwrap());
+    if audio.is_empty() {
+        return Err(anyhow!("No audio was recorded from the input device"));
+    }
+    Ok(audio)
+}
+
+/// Works out the noise floor, VAD threshold and input gain from a recording of
+/// the room and a recording of the user speaking
+fn analyze(noise: &[f32], speech: &[f32], sample_rate: u32) -> Result<Calibration> {
+    let noise_levels = frame_levels(noise, sample_rate);
+    let speech_levels = frame_levels(speech, sample_rate);
+
+    // The typical room level, ignoring the odd click
+    let noise_floor = percentile(&noise_levels, 0.5).ok_or_else(|| anyhow!("Noise recording is empty"))?;
+    // Nobody talks without pausing, so only the louder frames count as speech
+    let speech_level = percentile(&speech_levels, 0.8).ok_or_else(|| anyhow!("Speech recording is empty"))?;
+    let speech_peak = speech.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+
+    let margin_db = 20.0 * (speech_level / noise_floor.max(f32::EPSILON)).log10();
+    if margin_db < MIN_SPEECH_MARGIN_DB {
+        return Err(anyhow!(
+            "Speech was only {:.1} dB louder than the room; speak up or move closer to the microphone",
+            margin_db
+        ));
+    }
+
+    let (min_gain, max_gain) = GAIN_RANGE;
+    let mut input_gain = TARGET_SPEECH_RMS / speech_level;
+    if speech_peak > 0.0 {
+        input_gain = input_gain.min(MAX_PEAK / speech_peak);
+    }
+    let input_gain = input_gain.clamp(min_gain, max_gain);
+
+    Ok(Calibration {
+        noise_floor: noise_floor * input_gain,
+        threshold: (noise_floor * speech_level).sqrt() * input_gain,
+        input_gain,
+    })
+}
+
+/// RMS of each 10 ms frame
+fn frame_levels(audio: &[f32], sample_rate: u32) -> Vec<f32> {
+    let frame_len = (sample_rate * FRAME_MS / 1000).max(1) as usize;
+    audio
+        .chunks(frame_len)
+        .map(|frame| (frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32).sqrt())
+        .collect()
+}
+
+fn percentile(values: &[f32], fraction: f32) -> Option<f32> {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let index = ((sorted.len() as f32 - 1.0) * fraction).round() as usize;
+    sorted.get(index).copied()
+}
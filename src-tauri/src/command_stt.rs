@@ -0,0 +1,172 @@
+use crate::audio::{resample_audio, scrub};
+use crate::stt::{SttBackend, SttOptions, Transcript};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{Cursor, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+
+#[cfg(windows)]
+use std::os::windows::process::CommandExt;
+
+/// Sample rate of the WAV written to the command's stdin
+const COMMAND_SAMPLE_RATE: u32 = 16000;
+
+/// How to read the transcript from the command's stdout
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SttOutput {
+    /// The whole of stdout is the transcript
+    #[default]
+    Text,
+    /// stdout is a JSON object with a `text` and optional `language` field
+    Json,
+}
+
+/// An external recognizer run once per utterance. It receives 16 kHz mono
+/// 16-bit WAV on stdin and prints the transcript on stdout.
+///
+/// `{language}` in an argument is replaced by the configured language (or
+/// "auto") and `{task}` by "translate" or "transcribe".
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CommandSttConfig {
+    pub program: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub output: SttOutput,
+}
+
+impl CommandSttConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.program.trim().is_empty() {
+            return Err(anyhow!("STT command has no program"));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct JsonTranscript {
+    text: String,
+    #[serde(default)]
+    language: Option<String>,
+}
+
+/// STT backend that pipes each utterance through an external command
+pub struct CommandSttBackend {
+    config: CommandSttConfig,
+}
+
+impl CommandSttBackend {
+    pub fn new(config: CommandSttConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl SttBackend for CommandSttBackend {
+    fn transcribe(&self, audio_data: &[f32], sample_rate: u32, options: &SttOptions) -> Result<Transcript> {
+        let mut audio_16k = resample_audio(audio_data, sample_rate, COMMAND_SAMPLE_RATE)?;
+        let wav = encode_wav(&audio_16k);
+        scrub(&mut audio_16k);
+        let mut wav = wav?;
+
+        let task = if options.translate { "translate" } else { "transcribe" };
+        let args = self.config.args.iter().map(|arg| {
+            arg.replace("{language}", &options.language)
+                .replace("{task}", task)
+        });
+
+        let mut cmd = Command::new(&self.config.program);
+        cmd.args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        // Hide console window on Windows
+        #[cfg(windows)]
+        cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
+
+        let mut child = match cmd.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                scrub(&mut wav);
+                return Err(anyhow!("Failed to spawn {}: {}", self.config.program, e));
+            }
+        };
+
+        // Write on another thread while the output is drained here, so a command
+        // that prints before reading all of its input can't deadlock with us.
+        // Dropping stdin after writing closes it so the command sees end of input.
+        let writer = child.stdin.take().map(|mut stdin| {
+            thread::spawn(move || {
+                let result = stdin.write_all(&wav);
+                scrub(&mut wav);
+                result
+            })
+        });
+
+        let output = child.wait_with_output()?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(anyhow!("{} failed: {}", self.config.program, stderr));
+        }
+        if let Some(writer) = writer {
+            writer
+                .join()
+                .map_err(|_| anyhow!("Writing to {} panicked", self.config.program))?
+                .map_err(|e| anyhow!("Failed to write to {}: {}", self.config.program, e))?;
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let (text, detected) = match self.config.output {
+            SttOutput::Text => (stdout.trim().to_string(), None),
+            SttOutput::Json => {
+                let transcript: JsonTranscript = serde_json::from_str(stdout.trim())
+                    .context("STT command did not print a JSON object with a text field")?;
+                (transcript.text.trim().to_string(), transcript.language)
+            }
+        };
+
+        let language = if options.translate {
+            Some("en".to_string())
+        } else if options.is_auto() {
+            detected
+        } else {
+            Some(options.language.clone())
+        };
+
+        Ok(Transcript {
+            text,
+            language,
+            segments: Vec::new(),
+        })
+    }
+
+    fn is_ready(&self) -> bool {
+        true
+    }
+}
+
+/// Encodes mono f32 samples as a 16-bit WAV file in memory
+fn encode_wav(samples: &[f32]) -> Result<Vec<u8>> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: COMMAND_SAMPLE_RATE,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+
+    // Sized up front so growing it can't leave copies of the audio behind
+    let mut cursor = Cursor::new(Vec::with_capacity(44 + samples.len() * 2));
+    {
+        let mut writer = hound::WavWriter::new(&mut cursor, spec)?;
+        for sample in samples {
+            writer.write_sample((sample.clamp(-1.0, 1.0) * 32767.0) as i16)?;
+        }
+        writer.finalize()?;
+    }
+
+    Ok(cursor.into_inner())
+}
@@ -0,0 +1,277 @@
+use crate::audio::resample_audio;
+use crate::tts::{BackendKind, Speaker, SynthesisOptions, TtsBackend, Voice};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{Cursor, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+
+#[cfg(windows)]
+use std::os::windows::process::CommandExt;
+
+/// Audio format a command writes to stdout
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CommandOutput {
+    /// A WAV file; the header's format is used
+    #[default]
+    Wav,
+    /// Headerless 16-bit signed little-endian mono PCM at `sample_rate`
+    Raw,
+}
+
+/// A user-defined voice that runs a local program for every utterance, e.g.
+/// `espeak-ng --stdout -v en-us {text}`.
+///
+/// The placeholders `{text}`, `{speaker}`, `{length_scale}`, `{noise_scale}`,
+/// `{noise_w}` and `{sentence_silence}` are substituted in each argument. If no
+/// argument contains `{text}`, the text is written to the program's stdin.
+///
+/// Put `--` before a `{text}` argument where the program supports it. Text that
+/// would start an argument with `-` gets a leading space so it isn't parsed as
+/// a flag either way.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CommandVoiceConfig {
+    pub id: String,
+    pub name: String,
+    pub program: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub output: CommandOutput,
+    /// Rate of raw output, and the rate WAV output is converted to
+    #[serde(default = "default_sample_rate")]
+    pub sample_rate: u32,
+    #[serde(default)]
+    pub language: Option<String>,
+    #[serde(default = "default_num_speakers")]
+    pub num_speakers: u32,
+}
+
+fn default_sample_rate() -> u32 {
+    22050
+}
+
+fn default_num_speakers() -> u32 {
+    1
+}
+
+impl CommandVoiceConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.id.trim().is_empty() {
+            return Err(anyhow!("Command voice id is empty"));
+        }
+        if self.program.trim().is_empty() {
+            return Err(anyhow!("Command voice {} has no program", self.id));
+        }
+        if !(8000..=192000).contains(&self.sample_rate) {
+            return Err(anyhow!("Command voice {} has an invalid sample rate", self.id));
+        }
+        if self.num_speakers == 0 {
+            return Err(anyhow!("Command voice {} needs at least one speaker", self.id));
+        }
+        Ok(())
+    }
+
+    fn to_voice(&self) -> Voice {
+        let speakers = if self.num_speakers > 1 {
+            (0..self.num_speakers)
+                .map(|id| Speaker {
+                    id,
+                    name: format!("Speaker {}", id),
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        Voice {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            backend: BackendKind::Command,
+            sample_rate: self.sample_rate,
+            language: self.language.clone(),
+            quality: None,
+            num_speakers: self.num_speakers,
+            speakers,
+        }
+    }
+
+    /// Fills in the argument placeholders for one utterance
+    fn render_args(&self, text: &str, options: &SynthesisOptions) -> Vec<String> {
+        let speaker = options.speaker_id.unwrap_or(0).to_string();
+        let prosody = &options.prosody;
+        let operand = if text.starts_with('-') {
+            format!(" {}", text)
+        } else {
+            text.to_string()
+        };
+
+        self.args
+            .iter()
+            .map(|arg| {
+                arg.replace("{speaker}", &speaker)
+                    .replace("{length_scale}", &prosody.length_scale.to_string())
+                    .replace("{noise_scale}", &prosody.noise_scale.to_string())
+                    .replace("{noise_w}", &prosody.noise_w.to_string())
+                    .replace("{sentence_silence}", &prosody.sentence_silence.to_string())
+                    // Substitute the text last so it can't inject other placeholders
+                    .replace("{text}", if arg.starts_with("{text}") { &operand } else { text })
+            })
+            .collect()
+    }
+
+    fn text_in_args(&self) -> bool {
+        self.args.iter().any(|arg| arg.contains("{text}"))
+    }
+}
+
+/// TTS backend that runs a command template per utterance
+pub struct CommandBackend {
+    voices: Vec<CommandVoiceConfig>,
+}
+
+impl CommandBackend {
+    pub fn new() -> Self {
+        Self { voices: Vec::new() }
+    }
+
+    pub fn set_voices(&mut self, voices: Vec<CommandVoiceConfig>) {
+        self.voices = voices;
+    }
+
+    pub fn add_voice(&mut self, voice: CommandVoiceConfig) {
+        self.voices.push(voice);
+    }
+
+    pub fn remove_voice(&mut self, voice_id: &str) -> Result<()> {
+        let index = self
+            .voices
+            .iter()
+            .position(|v| v.id == voice_id)
+            .ok_or_else(|| anyhow!("Command voice not found: {}", voice_id))?;
+        self.voices.remove(index);
+        Ok(())
+    }
+}
+
+impl Default for CommandBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TtsBackend for CommandBackend {
+    fn list_voices(&self) -> Vec<Voice> {
+        self.voices.iter().map(|v| v.to_voice()).collect()
+    }
+
+    fn sample_rate(&self, voice_id: &str) -> Option<u32> {
+        self.voices
+            .iter()
+            .find(|v| v.id == voice_id)
+            .map(|v| v.sample_rate)
+    }
+
+    fn synthesize(&mut self, voice_id: &str, text: &str, options: &SynthesisOptions) -> Result<Vec<f32>> {
+        let config = self
+            .voices
+            .iter()
+            .find(|v| v.id == voice_id)
+            .ok_or_else(|| anyhow!("Command voice not found: {}", voice_id))?;
+
+        let text_on_stdin = !config.text_in_args();
+
+        let mut cmd = Command::new(&config.program);
+        cmd.args(config.render_args(text, options))
+            .stdin(if text_on_stdin { Stdio::piped() } else { Stdio::null() })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        // Hide console window on Windows
+        #[cfg(windows)]
+        cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
+
+        let mut child = cmd.spawn()
+            .map_err(|e| anyhow!("Failed to spawn {}: {}", config.program, e))?;
+
+        // Write on another thread while the output is drained here, so a command
+        // that prints before reading all of its input can't deadlock with us
+        let writer = child.stdin.take().map(|mut stdin| {
+            let text = text.to_string();
+            thread::spawn(move || stdin.write_all(text.as_bytes()))
+        });
+
+        let output = child.wait_with_output()?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(anyhow!("{} failed: {}", config.program, stderr));
+        }
+        if let Some(writer) = writer {
+            writer
+                .join()
+                .map_err(|_| anyhow!("Writing to {} panicked", config.program))?
+                .map_err(|e| anyhow!("Failed to write to {}: {}", config.program, e))?;
+        }
+
+        match config.output {
+            CommandOutput::Raw => Ok(decode_raw(&output.stdout)),
+            CommandOutput::Wav => {
+                let (samples, rate) = decode_wav(&output.stdout)?;
+                if rate == config.sample_rate {
+                    Ok(samples)
+                } else {
+                    resample_audio(&samples, rate, config.sample_rate)
+                }
+            }
+        }
+    }
+}
+
+/// Converts 16-bit signed little-endian PCM to f32
+fn decode_raw(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(2)
+        .map(|chunk| {
+            let sample = i16::from_le_bytes([chunk[0], chunk[1]]);
+            sample as f32 / 32768.0
+        })
+        .collect()
+}
+
+/// Decodes a WAV stream to mono f32 samples and its sample rate. Programs
+/// writing WAV to a pipe often leave the length fields unset, so decoding
+/// stops at the end of the data rather than failing on it.
+fn decode_wav(bytes: &[u8]) -> Result<(Vec<f32>, u32)> {
+    let reader = hound::WavReader::new(Cursor::new(bytes))
+        .context("Command output is not a WAV file")?;
+    let spec = reader.spec();
+    let channels = spec.channels.max(1) as usize;
+
+    let interleaved: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader
+            .into_samples::<f32>()
+            .map_while(|s| s.ok())
+            .collect(),
+        hound::SampleFormat::Int => {
+            let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .into_samples::<i32>()
+                .map_while(|s| s.ok())
+                .map(|s| s as f32 / scale)
+                .collect()
+        }
+    };
+
+    let mono = if channels > 1 {
+        interleaved
+            .chunks(channels)
+            .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+            .collect()
+    } else {
+        interleaved
+    };
+
+    Ok((mono, spec.sample_rate))
+}
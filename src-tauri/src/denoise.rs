@@ -0,0 +1,310 @@
+use crate::audio::{scrub, scrub_ring};
+use realfft::num_complex::Complex;
+use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// Target analysis frame length; the FFT size is the next power of two
+const FRAME_MS: u32 = 20;
+/// Weight of the previous frame when smoothing each bin's power
+const POWER_SMOOTHING: f32 = 0.7;
+/// The noise estimate is the quietest smoothed power seen over this long.
+/// Anything steady for longer, like a fan, ends up counted as noise.
+const MIN_WINDOW_MS: f32 = 1500.0;
+/// The minimum is tracked over this many sub-windows, so it can rise again
+const SUB_WINDOWS: usize = 6;
+/// The minimum of a noisy power sits below its mean; this corrects for it
+const MIN_BIAS: f32 = 2.0;
+/// Weight of the previous frame's cleaned power in the speech-to-noise
+/// estimate. High values keep isolated noise peaks from popping through.
+const PRIOR_WEIGHT: f32 = 0.98;
+/// Lowest gain applied to a bin. Leaving a little noise in sounds more
+/// natural and avoids the "musical" artifacts of full removal.
+const GAIN_FLOOR: f32 = 0.1;
+
+/// Spectral noise suppressor for a mono stream.
+///
+/// The signal is cut into half-overlapping frames, each bin's noise power is
+/// estimated from its recent minimum, and every bin is scaled by a Wiener gain
+/// so bins that are mostly noise are turned down. Output is delayed by `latency()`
+/// samples but always comes out in blocks the size of the input.
+pub struct NoiseSuppressor {
+    forward: Arc<dyn RealToComplex<f32>>,
+    inverse: Arc<dyn ComplexToReal<f32>>,
+    /// Square-root Hann window, used for both analysis and synthesis
+    window: Vec<f32>,
+    hop: usize,
+    /// The most recent frame of input
+    frame: Vec<f32>,
+    /// Input not yet part of a frame
+    pending: Vec<f32>,
+    /// Overlap-add accumulator
+    overlap: Vec<f32>,
+    output: VecDeque<f32>,
+    /// Smoothed power per bin
+    smoothed: Vec<f32>,
+    /// Minimum smoothed power per bin in the current sub-window
+    current_min: Vec<f32>,
+    /// Minimums of the most recent finished sub-windows
+    window_mins: VecDeque<Vec<f32>>,
+    sub_window_frames: usize,
+    frames_in_window: usize,
+    gains: Vec<f32>,
+    /// Each bin's power in the previous frame
+    previous_power: Vec<f32>,
+    time: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+    forward_scratch: Vec<Complex<f32>>,
+    inverse_scratch: Vec<Complex<f32>>,
+}
+
+impl NoiseSuppressor {
+    pub fn new(sample_rate: u32) -> Self {
+        let fft_len = ((sample_rate * FRAME_MS / 1000) as usize).next_power_of_two().max(64);
+        let hop = fft_len / 2;
+        let hop_ms = hop as f32 * 1000.0 / sample_rate as f32;
+
+        let mut planner = RealFftPlanner::<f32>::new();
+        let forward = planner.plan_fft_forward(fft_len);
+        let inverse = planner.plan_fft_inverse(fft_len);
+        let bins = fft_len / 2 + 1;
+
+        let window = (0..fft_len)
+            .map(|n| (std::f32::consts::PI * n as f32 / fft_len as f32).sin())
+            .collect();
+
+        let mut suppressor = Self {
+            forward_scratch: forward.make_scratch_vec(),
+            inverse_scratch: inverse.make_scratch_vec(),
+            time: forward.make_input_vec(),
+            spectrum: forward.make_output_vec(),
+            forward,
+            inverse,
+            window,
+            hop,
+            frame: vec![0.0; fft_len],
+            pending: Vec::with_capacity(hop * 2),
+            overlap: vec![0.0; fft_len],
+            output: VecDeque::new(),
+            smoothed: vec![0.0; bins],
+            current_min: vec![f32::MAX; bins],
+            window_mins: VecDeque::with_capacity(SUB_WINDOWS),
+            sub_window_frames: ((MIN_WINDOW_MS / SUB_WINDOWS as f32 / hop_ms) as usize).max(1),
+            frames_in_window: 0,
+            gains: vec![1.0; bins],
+            previous_power: vec![0.0; bins],
+        };
+        suppressor.reset();
+        suppressor
+    }
+
+    /// How many samples the output lags behind the input
+    pub fn latency(&self) -> usize {
+        self.frame.len()
+    }
+
+    /// Forgets the signal and the noise estimate, e.g. after being bypassed
+    pub fn reset(&mut self) {
+        self.frame.fill(0.0);
+        scrub(&mut self.pending);
+        self.pending.clear();
+        self.overlap.fill(0.0);
+        // One hop of silence up front means a full block is always ready
+        scrub_ring(&mut self.output);
+        self.output.resize(self.hop, 0.0);
+        self.smoothed.fill(0.0);
+        self.current_min.fill(f32::MAX);
+        self.window_mins.clear();
+        self.frames_in_window = 0;
+        self.gains.fill(1.0);
+        self.previous_power.fill(0.0);
+    }
+
+    /// Denoises a block of samples, returning a block of the same length
+    pub fn process(&mut self, input: &[f32]) -> Vec<f32> {
+        self.pending.extend_from_slice(input);
+
+        let mut consumed = 0;
+        while self.pending.len() - consumed >= self.hop {
+            let fft_len = self.frame.len();
+            self.frame.copy_within(self.hop.., 0);
+            self.frame[fft_len - self.hop..].copy_from_slice(&self.pending[consumed..consumed + self.hop]);
+            consumed += self.hop;
+            self.process_frame();
+        }
+        self.pending.drain(..consumed);
+
+        self.output.drain(..input.len()).collect()
+    }
+
+    fn process_frame(&mut self) {
+        let fft_len = self.frame.len();
+        for ((t, s), w) in self.time.iter_mut().zip(&self.frame).zip(&self.window) {
+            *t = s * w;
+        }
+        // Buffer sizes are fixed at construction, so the transforms can't fail
+        self.forward
+            .process_with_scratch(&mut self.time, &mut self.spectrum, &mut self.forward_scratch)
+            .expect("FFT buffers have the planned sizes");
+
+        let first_frame = self.window_mins.is_empty() && self.frames_in_window == 0;
+        for (k, bin) in self.spectrum.iter_mut().enumerate() {
+            let power = bin.norm_sqr();
+
+            let smoothed = &mut self.smoothed[k];
+            *smoothed = if first_frame {
+                power
+            } else {
+                POWER_SMOOTHING * *smoothed + (1.0 - POWER_SMOOTHING) * power
+            };
+            self.current_min[k] = self.current_min[k].min(*smoothed);
+
+            let noise = self
+                .window_mins
+                .iter()
+                .map(|mins| mins[k])
+                .fold(self.current_min[k], f32::min);
+            // Kept above zero so digital silence doesn't divide by zero
+            let noise = (MIN_BIAS * noise).max(1e-10);
+
+            // Wiener gain from a decision-directed estimate of the speech-to-noise ratio
+            let gain = &mut self.gains[k];
+            let previous = *gain * *gain * self.previous_power[k] / noise;
+            let current = (power / noise - 1.0).max(0.0);
+            let snr = PRIOR_WEIGHT * previous + (1.0 - PRIOR_WEIGHT) * current;
+            *gain = (snr / (1.0 + snr)).max(GAIN_FLOOR);
+            self.previous_power[k] = power;
+            *bin *= *gain;
+        }
+
+        self.frames_in_window += 1;
+        if self.frames_in_window >= self.sub_window_frames {
+            if self.window_mins.len() >= SUB_WINDOWS {
+                self.window_mins.pop_front();
+            }
+            self.window_mins.push_back(self.current_min.clone());
+            self.current_min.fill(f32::MAX);
+            self.frames_in_window = 0;
+        }
+
+        // The inverse transform expects purely real DC and Nyquist bins
+        if let Some(first) = self.spectrum.first_mut() {
+            first.im = 0.0;
+        }
+        if let Some(last) = self.spectrum.last_mut() {
+            last.im = 0.0;
+        }
+        self.inverse
+            .process_with_scratch(&mut self.spectrum, &mut self.time, &mut self.inverse_scratch)
+            .expect("FFT buffers have the planned sizes");
+
+        let scale = 1.0 / fft_len as f32;
+        for ((acc, t), w) in self.overlap.iter_mut().zip(&self.time).zip(&self.window) {
+            *acc += t * w * scale;
+        }
+
+        self.output.extend(&self.overlap[..self.hop]);
+        self.overlap.copy_within(self.hop.., 0);
+        self.overlap[fft_len - self.hop..].fill(0.0);
+    }
+}
+
+impl Drop for NoiseSuppressor {
+    /// Wipes the microphone audio still held in the frame buffers
+    fn drop(&mut self) {
+        for buffer in [&mut self.frame, &mut self.pending, &mut self.overlap, &mut self.time] {
+            scrub(buffer);
+        }
+        for buffer in [&mut self.spectrum, &mut self.forward_scratch, &mut self.inverse_scratch] {
+            scrub(buffer);
+        }
+        scrub_ring(&mut self.output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 16000;
+
+    /// A second of silence, then a voiced, syllable-like signal
+    fn clean_signal() -> Vec<f32> {
+        let silence = RATE as usize;
+        let voiced = 2 * RATE as usize;
+        let mut signal = vec![0.0; silence];
+        signal.extend((0..voiced).map(|i| {
+            let t = i as f32 / RATE as f32;
+            let envelope = 0.5 - 0.5 * (2.0 * std::f32::consts::PI * 4.0 * t).cos();
+            let voice: f32 = (1..=10)
+                .map(|k| (2.0 * std::f32::consts::PI * 150.0 * k as f32 * t).sin() / k as f32)
+                .sum();
+            0.05 * envelope * voice
+        }));
+        signal
+    }
+
+    /// Deterministic white noise
+    fn noise(len: usize, amplitude: f32) -> Vec<f32> {
+        let mut state: u32 = 0x2545_f491;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                amplitude * (state as f32 / u32::MAX as f32 * 2.0 - 1.0)
+            })
+            .collect()
+    }
+
+    fn snr_db(clean: &[f32], signal: &[f32]) -> f32 {
+        let power: f32 = clean.iter().map(|s| s * s).sum();
+        let error: f32 = clean.iter().zip(signal).map(|(c, s)| (c - s) * (c - s)).sum();
+        10.0 * (power / error).log10()
+    }
+
+    #[test]
+    fn improves_snr_of_noisy_wav() {
+        let clean = clean_signal();
+        let noisy: Vec<f32> = clean.iter().zip(noise(clean.len(), 0.05)).map(|(c, n)| c + n).collect();
+
+        let path = std::env::temp_dir().join(format!("parrot-denoise-{}.wav", std::process::id()));
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: RATE,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for sample in &noisy {
+            writer.write_sample(*sample).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let mut reader = hound::WavReader::open(&path).unwrap();
+        let input: Vec<f32> = reader.samples::<f32>().map(|s| s.unwrap()).collect();
+        std::fs::remove_file(&path).unwrap();
+
+        // Feed it the way the input callback would, in 10 ms blocks
+        let mut suppressor = NoiseSuppressor::new(RATE);
+        let mut output = Vec::with_capacity(input.len());
+        for block in input.chunks(160) {
+            let denoised = suppressor.process(block);
+            assert_eq!(denoised.len(), block.len());
+            output.extend(denoised);
+        }
+
+        // Skip the first half second while the noise estimate settles
+        let start = RATE as usize / 2;
+        let latency = suppressor.latency();
+        let before = snr_db(&clean[start..], &input[start..]);
+        let after = snr_db(&clean[start..clean.len() - latency], &output[start + latency..]);
+
+        assert!(
+            after - before >= 6.0,
+            "SNR went from {:.1} dB to {:.1} dB",
+            before,
+            after
+        );
+    }
+}
@@ -0,0 +1,529 @@
+use anyhow::{anyhow, Result};
+use rand::Rng;
+use realfft::num_complex::Complex;
+use realfft::RealFftPlanner;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+/// Phase vocoder frame length at 22.05 kHz, scaled with the sample rate
+const VOCODER_FRAME_MS: u32 = 46;
+/// Frames overlap by this factor; 4 keeps the phase vocoder smooth
+const VOCODER_OVERLAP: usize = 4;
+/// Width of the smoothing that separates the spectral envelope (the
+/// formants) from the harmonics
+const ENVELOPE_HZ: f32 = 300.0;
+/// EQ band centres
+const LOW_SHELF_HZ: f32 = 250.0;
+const MID_PEAK_HZ: f32 = 1500.0;
+const HIGH_SHELF_HZ: f32 = 4000.0;
+const MID_PEAK_Q: f32 = 1.0;
+/// Output is scaled down if it would peak above this
+const MAX_PEAK: f32 = 0.99;
+
+/// Three-band equalizer, gains in dB
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Equalizer {
+    pub low_db: f32,
+    pub mid_db: f32,
+    pub high_db: f32,
+}
+
+/// Effect applied last in the chain
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Effect {
+    #[default]
+    None,
+    /// Flattens the voice to a monotone buzz at `pitch_hz`
+    Robot { pitch_hz: f32 },
+    /// Multiplies the voice with a sine wave, blended in by `mix`
+    RingMod { frequency_hz: f32, mix: f32 },
+}
+
+/// Voice disguise applied to synthesized speech
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DisguiseSettings {
+    /// Pitch shift in semitones, keeping the formants in place
+    pub pitch_semitones: f32,
+    /// Formant shift in semitones; positive sounds smaller, negative larger
+    pub formant_semitones: f32,
+    /// Playback speed without changing pitch; above 1.0 is faster
+    pub speed: f32,
+    pub eq: Equalizer,
+    pub effect: Effect,
+}
+
+impl Default for DisguiseSettings {
+    fn default() -> Self {
+        Self {
+            pitch_semitones: 0.0,
+            formant_semitones: 0.0,
+            speed: 1.0,
+            eq: Equalizer::default(),
+            effect: Effect::None,
+        }
+    }
+}
+
+impl DisguiseSettings {
+    pub fn validate(&self) -> Result<()> {
+        if !(-12.0..=12.0).contains(&self.pitch_semitones) {
+            return Err(anyhow!("Pitch shift must be between -12 and 12 semitones"));
+        }
+        if !(-6.0..=6.0).contains(&self.formant_semitones) {
+            return Err(anyhow!("Formant shift must be between -6 and 6 semitones"));
+        }
+        if !(0.5..=2.0).contains(&self.speed) {
+            return Err(anyhow!("Speed must be between 0.5 and 2.0"));
+        }
+        let eq = [self.eq.low_db, self.eq.mid_db, self.eq.high_db];
+        if eq.iter().any(|db| !(-12.0..=12.0).contains(db)) {
+            return Err(anyhow!("EQ gains must be between -12 and 12 dB"));
+        }
+        match self.effect {
+            Effect::None => {}
+            Effect::Robot { pitch_hz } => {
+                if !(50.0..=400.0).contains(&pitch_hz) {
+                    return Err(anyhow!("Robot pitch must be between 50 and 400 Hz"));
+                }
+            }
+            Effect::RingMod { frequency_hz, mix } => {
+                if !(10.0..=2000.0).contains(&frequency_hz) {
+                    return Err(anyhow!("Ring modulator frequency must be between 10 and 2000 Hz"));
+                }
+                if !(0.0..=1.0).contains(&mix) {
+                    return Err(anyhow!("Ring modulator mix must be between 0 and 1"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the settings leave the voice untouched
+    pub fn is_neutral(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// A random disguise that still sounds natural: a clear pitch shift up or
+    /// down, with smaller formant, speed and EQ changes
+    pub fn random(rng: &mut impl Rng) -> Self {
+        let direction = if rng.gen_bool(0.5) { 1.0 } else { -1.0 };
+        Self {
+            pitch_semitones: direction * rng.gen_range(1.0..=3.0),
+            formant_semitones: rng.gen_range(-1.5..=1.5),
+            speed: rng.gen_range(0.95..=1.05),
+            eq: Equalizer {
+                low_db: rng.gen_range(-3.0..=3.0),
+                mid_db: rng.gen_range(-3.0..=3.0),
+                high_db: rng.gen_range(-3.0..=3.0),
+            },
+            effect: Effect::None,
+        }
+    }
+}
+
+/// Runs a synthesized utterance through the disguise chain: pitch, formant
+/// and speed changes, EQ, then the effect
+pub fn apply(audio: &[f32], sample_rate: u32, settings: &DisguiseSettings) -> Vec<f32> {
+    let pitch = semitones_to_ratio(settings.pitch_semitones);
+    let formant = semitones_to_ratio(settings.formant_semitones);
+
+    let mut output = if pitch != 1.0 || formant != 1.0 || settings.speed != 1.0 {
+        phase_vocoder(audio, sample_rate, pitch, formant, settings.speed)
+    } else {
+        audio.to_vec()
+    };
+
+    if settings.eq != Equalizer::default() {
+        let bands = [
+            Biquad::low_shelf(sample_rate, LOW_SHELF_HZ, settings.eq.low_db),
+            Biquad::peaking(sample_rate, MID_PEAK_HZ, MID_PEAK_Q, settings.eq.mid_db),
+            Biquad::high_shelf(sample_rate, HIGH_SHELF_HZ, settings.eq.high_db),
+        ];
+        for mut band in bands.into_iter().flatten() {
+            band.process(&mut output);
+        }
+    }
+
+    match settings.effect {
+        Effect::None => {}
+        Effect::Robot { pitch_hz } => output = robotize(&output, sample_rate, pitch_hz),
+        Effect::RingMod { frequency_hz, mix } => {
+            let step = 2.0 * PI * frequency_hz / sample_rate as f32;
+            for (i, sample) in output.iter_mut().enumerate() {
+                *sample *= 1.0 - mix + mix * (step * i as f32).sin();
+            }
+        }
+    }
+
+    let peak = output.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+    if peak > MAX_PEAK {
+        let scale = MAX_PEAK / peak;
+        output.iter_mut().for_each(|s| *s *= scale);
+    }
+    output
+}
+
+fn semitones_to_ratio(semitones: f32) -> f32 {
+    2f32.powf(semitones / 12.0)
+}
+
+/// Shifts pitch and formants independently and changes speed.
+///
+/// Each frame's spectrum is split into a smooth envelope (the formants) and
+/// the harmonics riding on it. The harmonics are moved by `pitch`, the
+/// envelope by `formant`, and frames are laid out `speed` times closer
+/// together. Each spectral peak's phase is advanced to match its frequency
+/// and the bins around it keep their phase relative to the peak, which keeps
+/// the voice from sounding phasey.
+fn phase_vocoder(audio: &[f32], sample_rate: u32, pitch: f32, formant: f32, speed: f32) -> Vec<f32> {
+    let fft_len = ((sample_rate * VOCODER_FRAME_MS / 1000) as usize).next_power_of_two();
+    let analysis_hop = fft_len / VOCODER_OVERLAP;
+    let synthesis_hop = ((analysis_hop as f32 / speed).round() as usize).max(1);
+    let bins = fft_len / 2 + 1;
+    let envelope_bins = ((ENVELOPE_HZ * fft_len as f32 / sample_rate as f32) as usize).max(1);
+
+    let mut last_phase = vec![0.0f32; bins];
+    let mut phase = vec![0.0f32; bins];
+    let mut frequency = vec![0.0f32; bins];
+    let mut magnitude = vec![0.0f32; bins];
+    let mut output_phase = vec![0.0f32; bins];
+    let mut shifted = vec![0.0f32; bins];
+    // Analysis bin each output bin was taken from
+    let mut source = vec![0usize; bins];
+    let mut peaks = Vec::with_capacity(bins);
+
+    resynthesize(audio, fft_len, analysis_hop, synthesis_hop, |spectrum| {
+        // Analysis: each bin's magnitude, phase and true frequency, in bins
+        for (k, bin) in spectrum.iter().enumerate() {
+            phase[k] = bin.arg();
+            let expected = 2.0 * PI * k as f32 * analysis_hop as f32 / fft_len as f32;
+            let deviation = wrap_phase(phase[k] - last_phase[k] - expected);
+            frequency[k] = k as f32 + deviation * fft_len as f32 / (2.0 * PI * analysis_hop as f32);
+            magnitude[k] = bin.norm();
+        }
+        last_phase.copy_from_slice(&phase);
+
+        let envelope = smooth(&magnitude, envelope_bins);
+
+        // Move the harmonics by the pitch ratio and give them the envelope
+        // moved by the formant ratio. Where several bins land on one, the
+        // strongest wins.
+        shifted.fill(0.0);
+        for k in 0..bins {
+            let target = (k as f32 * pitch).round() as usize;
+            if target >= bins {
+                break;
+            }
+            let excitation = magnitude[k] / envelope[k].max(1e-9);
+            let value = excitation * interpolate(&envelope, target as f32 / formant);
+            if value >= shifted[target] {
+                shifted[target] = value;
+                source[target] = k;
+            }
+        }
+
+        // Synthesis: peaks advance by their frequency over the output hop,
+        // the bins around each peak keep their phase relative to it
+        peaks.clear();
+        peaks.extend((0..bins).filter(|&k| {
+            let lo = k.saturating_sub(2);
+            let hi = (k + 3).min(bins);
+            shifted[k] > 0.0 && shifted[lo..hi].iter().all(|&m| m <= shifted[k])
+        }));
+        for &k in &peaks {
+            let advance = 2.0 * PI * frequency[source[k]] * pitch * synthesis_hop as f32 / fft_len as f32;
+            output_phase[k] = wrap_phase(output_phase[k] + advance);
+        }
+        let mut nearest = 0;
+        for k in 0..bins {
+            while nearest + 1 < peaks.len() && peaks[nearest + 1].abs_diff(k) < peaks[nearest].abs_diff(k) {
+                nearest += 1;
+            }
+            match peaks.get(nearest) {
+                Some(&peak) if peak != k => {
+                    output_phase[k] = output_phase[peak] + phase[source[k]] - phase[source[peak]];
+                }
+                Some(_) => {}
+                None => output_phase[k] = phase[source[k]],
+            }
+            spectrum[k] = Complex::from_polar(shifted[k], output_phase[k]);
+        }
+    })
+}
+
+/// Classic robot voice: every frame is given zero phase and frames are laid
+/// out one pitch period apart, so the voice buzzes at a single pitch
+fn robotize(audio: &[f32], sample_rate: u32, pitch_hz: f32) -> Vec<f32> {
+    let hop = ((sample_rate as f32 / pitch_hz).round() as usize).max(1);
+    let fft_len = (hop * 2).next_power_of_two().max(1024);
+
+    resynthesize(audio, fft_len, hop, hop, |spectrum| {
+        // Zero phase, centred in the frame so the window doesn't cut the pulse
+        for (k, bin) in spectrum.iter_mut().enumerate() {
+            let sign = if k % 2 == 0 { 1.0 } else { -1.0 };
+            *bin = Complex::new(sign * bin.norm(), 0.0);
+        }
+    })
+}
+
+/// Short-time Fourier resynthesis. Frames are read `analysis_hop` apart,
+/// passed to `modify` as spectra, and overlap-added `synthesis_hop` apart, so
+/// the output is `synthesis_hop / analysis_hop` times as long as the input.
+fn resynthesize<F>(audio: &[f32], fft_len: usize, analysis_hop: usize, synthesis_hop: usize, mut modify: F) -> Vec<f32>
+where
+    F: FnMut(&mut [Complex<f32>]),
+{
+    let mut planner = RealFftPlanner::<f32>::new();
+    let forward = planner.plan_fft_forward(fft_len);
+    let inverse = planner.plan_fft_inverse(fft_len);
+    let mut time = forward.make_input_vec();
+    let mut spectrum = forward.make_output_vec();
+
+    let window: Vec<f32> = (0..fft_len)
+        .map(|n| 0.5 - 0.5 * (2.0 * PI * n as f32 / fft_len as f32).cos())
+        .collect();
+
+    // Pad a frame of silence on both sides so the edges get full coverage
+    let mut padded = vec![0.0; fft_len];
+    padded.extend_from_slice(audio);
+    padded.resize(padded.len() + fft_len, 0.0);
+
+    let frames = (padded.len() - fft_len) / analysis_hop + 1;
+    let output_len = (frames - 1) * synthesis_hop + fft_len;
+    let mut output = vec![0.0f32; output_len];
+    let mut weight = vec![0.0f32; output_len];
+
+    for frame in 0..frames {
+        let start = frame * analysis_hop;
+        for ((t, s), w) in time.iter_mut().zip(&padded[start..start + fft_len]).zip(&window) {
+            *t = s * w;
+        }
+        // Buffer sizes come from the planner, so the transforms can't fail
+        forward.process(&mut time, &mut spectrum).expect("FFT buffers have the planned sizes");
+
+        modify(&mut spectrum);
+        // The inverse transform expects purely real DC and Nyquist bins
+        spectrum[0].im = 0.0;
+        if let Some(last) = spectrum.last_mut() {
+            last.im = 0.0;
+        }
+
+        inverse.process(&mut spectrum, &mut time).expect("FFT buffers have the planned sizes");
+
+        let out_start = frame * synthesis_hop;
+        for (i, (t, w)) in time.iter().zip(&window).enumerate() {
+            output[out_start + i] += t * w / fft_len as f32;
+            weight[out_start + i] += w * w;
+        }
+    }
+
+    for (sample, weight) in output.iter_mut().zip(&weight) {
+        if *weight > 1e-3 {
+            *sample /= weight;
+        }
+    }
+
+    // Drop the padding, scaled to the output's time base
+    let skip = fft_len * synthesis_hop / analysis_hop;
+    let len = (audio.len() as f32 * synthesis_hop as f32 / analysis_hop as f32).round() as usize;
+    output.into_iter().skip(skip).take(len).collect()
+}
+
+fn wrap_phase(phase: f32) -> f32 {
+    phase - 2.0 * PI * (phase / (2.0 * PI)).round()
+}
+
+/// Moving average over `width` bins on each side
+fn smooth(values: &[f32], width: usize) -> Vec<f32> {
+    (0..values.len())
+        .map(|i| {
+            let lo = i.saturating_sub(width);
+            let hi = (i + width + 1).min(values.len());
+            values[lo..hi].iter().sum::<f32>() / (hi - lo) as f32
+        })
+        .collect()
+}
+
+/// Linear interpolation at a fractional index, zero past the end
+fn interpolate(values: &[f32], index: f32) -> f32 {
+    let i = index.floor() as usize;
+    let frac = index - i as f32;
+    match (values.get(i), values.get(i + 1)) {
+        (Some(a), Some(b)) => a + (b - a) * frac,
+        (Some(a), None) => *a,
+        _ => 0.0,
+    }
+}
+
+/// Second-order IIR filter from the RBJ audio EQ cookbook
+struct Biquad {
+    b: [f32; 3],
+    a: [f32; 2],
+    x: [f32; 2],
+    y: [f32; 2],
+}
+
+impl Biquad {
+    /// Normalizes the coefficients by a0. Returns `None` for a flat band or a
+    /// frequency the sample rate can't represent.
+    fn new(sample_rate: u32, freq: f32, gain_db: f32, coefficients: impl Fn(f32, f32, f32) -> [f32; 6]) -> Option<Self> {
+        if gain_db == 0.0 || freq >= sample_rate as f32 / 2.0 {
+            return None;
+        }
+        let a = 10f32.powf(gain_db / 40.0);
+        let w0 = 2.0 * PI * freq / sample_rate as f32;
+        let [b0, b1, b2, a0, a1, a2] = coefficients(a, w0.cos(), w0.sin());
+        Some(Self {
+            b: [b0 / a0, b1 / a0, b2 / a0],
+            a: [a1 / a0, a2 / a0],
+            x: [0.0; 2],
+            y: [0.0; 2],
+        })
+    }
+
+    fn low_shelf(sample_rate: u32, freq: f32, gain_db: f32) -> Option<Self> {
+        Self::new(sample_rate, freq, gain_db, |a, cos, sin| {
+            let alpha = sin / 2.0 * 2f32.sqrt();
+            let s = 2.0 * a.sqrt() * alpha;
+            [
+                a * ((a + 1.0) - (a - 1.0) * cos + s),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - s),
+                (a + 1.0) + (a - 1.0) * cos + s,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - s,
+            ]
+        })
+    }
+
+    fn high_shelf(sample_rate: u32, freq: f32, gain_db: f32) -> Option<Self> {
+        Self::new(sample_rate, freq, gain_db, |a, cos, sin| {
+            let alpha = sin / 2.0 * 2f32.sqrt();
+            let s = 2.0 * a.sqrt() * alpha;
+            [
+                a * ((a + 1.0) + (a - 1.0) * cos + s),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - s),
+                (a + 1.0) - (a - 1.0) * cos + s,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - s,
+            ]
+        })
+    }
+
+    fn peaking(sample_rate: u32, freq: f32, q: f32, gain_db: f32) -> Option<Self> {
+        Self::new(sample_rate, freq, gain_db, |a, cos, sin| {
+            let alpha = sin / (2.0 * q);
+            [
+                1.0 + alpha * a,
+                -2.0 * cos,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos,
+                1.0 - alpha / a,
+            ]
+        })
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples.iter_mut() {
+            let x = *sample;
+            let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+                - self.a[0] * self.y[0]
+                - self.a[1] * self.y[1];
+            self.x = [x, self.x[0]];
+            self.y = [y, self.y[0]];
+            *sample = y;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 22050;
+
+    fn sine(frequency: f32, seconds: f32) -> Vec<f32> {
+        (0..(seconds * RATE as f32) as usize)
+            .map(|i| 0.5 * (2.0 * PI * frequency * i as f32 / RATE as f32).sin())
+            .collect()
+    }
+
+    /// Frequency from the rising zero crossings of the middle half, away from
+    /// the edges where the frames fade in and out
+    fn frequency(signal: &[f32]) -> f32 {
+        let middle = &signal[signal.len() / 4..signal.len() * 3 / 4];
+        let crossings: Vec<usize> = middle
+            .windows(2)
+            .enumerate()
+            .filter(|(_, pair)| pair[0] < 0.0 && pair[1] >= 0.0)
+            .map(|(i, _)| i)
+            .collect();
+        let periods = crossings.len() - 1;
+        let span = crossings[periods] - crossings[0];
+        periods as f32 * RATE as f32 / span as f32
+    }
+
+    #[test]
+    fn pitch_shift_moves_frequency_by_ratio() {
+        let input = sine(220.0, 1.0);
+        for semitones in [-5.0, 3.0, 7.0] {
+            let settings = DisguiseSettings {
+                pitch_semitones: semitones,
+                ..Default::default()
+            };
+            let output = apply(&input, RATE, &settings);
+
+            let expected = 220.0 * semitones_to_ratio(semitones);
+            let measured = frequency(&output);
+            assert_eq!(output.len(), input.len());
+            assert!(
+                (measured / expected - 1.0).abs() < 0.01,
+                "{} semitones gave {:.1} Hz, expected {:.1} Hz",
+                semitones,
+                measured,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn speed_changes_length_but_not_pitch() {
+        let input = sine(220.0, 1.0);
+        let settings = DisguiseSettings {
+            speed: 1.25,
+            ..Default::default()
+        };
+        let output = apply(&input, RATE, &settings);
+
+        // The output hop is rounded to whole samples, so the ratio is close but not exact
+        assert!((output.len() as f32 * 1.25 / input.len() as f32 - 1.0).abs() < 0.01);
+        assert!((frequency(&output) / 220.0 - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn neutral_settings_leave_the_signal_unchanged() {
+        let input = sine(220.0, 0.5);
+        assert!(DisguiseSettings::default().is_neutral());
+        assert_eq!(apply(&input, RATE, &DisguiseSettings::default()), input);
+
+        // Flat EQ and a ring modulator mixed all the way out are bypassed too
+        let bypassed = DisguiseSettings {
+            eq: Equalizer {
+                low_db: 0.0,
+                mid_db: 0.0,
+                high_db: 0.0,
+            },
+            effect: Effect::RingMod {
+                frequency_hz: 50.0,
+                mix: 0.0,
+            },
+            ..Default::default()
+        };
+        assert_eq!(apply(&input, RATE, &bypassed), input);
+    }
+}
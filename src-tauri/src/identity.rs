@@ -0,0 +1,335 @@
+use crate::disguise::DisguiseSettings;
+use crate::pipeline::PipelineState;
+use crate::settings::Settings;
+use crate::tts::{Prosody, Voice};
+use anyhow::{anyhow, Context, Result};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Emitter, Runtime};
+
+/// Inclusive range a random value is drawn from
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ValueRange {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl ValueRange {
+    fn sample(&self, rng: &mut impl Rng) -> f32 {
+        if self.min < self.max {
+            rng.gen_range(self.min..=self.max)
+        } else {
+            self.min
+        }
+    }
+}
+
+/// Picks a random voice identity for every session
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IdentitySettings {
+    pub enabled: bool,
+    /// Also pick a new identity this often while the pipeline runs; 0 keeps
+    /// one identity for the whole session
+    pub rotate_minutes: u32,
+    /// Voice ids to choose from; empty allows every voice
+    pub voices: Vec<String>,
+    /// Disguise presets to choose from; empty allows every saved preset
+    pub disguise_presets: Vec<String>,
+    pub length_scale: ValueRange,
+    pub noise_scale: ValueRange,
+    pub noise_w: ValueRange,
+}
+
+impl Default for IdentitySettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            rotate_minutes: 0,
+            voices: Vec::new(),
+            disguise_presets: Vec::new(),
+            length_scale: ValueRange { min: 0.85, max: 1.2 },
+            noise_scale: ValueRange { min: 0.4, max: 0.8 },
+            noise_w: ValueRange { min: 0.6, max: 1.0 },
+        }
+    }
+}
+
+impl IdentitySettings {
+    pub fn validate(&self) -> Result<()> {
+        if self.rotate_minutes > 24 * 60 {
+            return Err(anyhow!("Identity rotation must be at most once a day"));
+        }
+        let ranges = [
+            ("length_scale", self.length_scale),
+            ("noise_scale", self.noise_scale),
+            ("noise_w", self.noise_w),
+        ];
+        for (name, range) in ranges {
+            if range.min > range.max {
+                return Err(anyhow!("{} range minimum is above its maximum", name));
+            }
+        }
+        // Both ends of each range have to be valid prosody
+        let ends = [
+            (self.length_scale.min, self.noise_scale.min, self.noise_w.min),
+            (self.length_scale.max, self.noise_scale.max, self.noise_w.max),
+        ];
+        for (length_scale, noise_scale, noise_w) in ends {
+            Prosody {
+                length_scale,
+                noise_scale,
+                noise_w,
+                ..Prosody::default()
+            }
+            .validate()?;
+        }
+        Ok(())
+    }
+
+    fn rotate_every(&self) -> Option<Duration> {
+        (self.rotate_minutes > 0).then(|| Duration::from_secs(self.rotate_minutes as u64 * 60))
+    }
+}
+
+/// The voice a session speaks with
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Identity {
+    pub voice_id: String,
+    pub speaker_id: Option<u32>,
+    pub prosody: Prosody,
+    pub disguise_preset: Option<String>,
+}
+
+impl Identity {
+    /// Draws an identity from the allowed voices, presets and prosody ranges.
+    /// Sentence silence is taken from `base`, since it doesn't identify anyone.
+    pub fn choose(
+        settings: &IdentitySettings,
+        voices: &[Voice],
+        presets: &HashMap<String, DisguiseSettings>,
+        base: Prosody,
+        rng: &mut impl Rng,
+    ) -> Result<Self> {
+        let voices: Vec<&Voice> = voices
+            .iter()
+            .filter(|v| settings.voices.is_empty() || settings.voices.contains(&v.id))
+            .collect();
+        let voice = voices
+            .choose(rng)
+            .ok_or_else(|| anyhow!("None of the allowed voices are available"))?;
+        let speaker_id = voice
+            .is_multi_speaker()
+            .then(|| rng.gen_range(0..voice.num_speakers));
+
+        let prosody = Prosody {
+            length_scale: settings.length_scale.sample(rng),
+            noise_scale: settings.noise_scale.sample(rng),
+            noise_w: settings.noise_w.sample(rng),
+            ..base
+        };
+
+        // Sorted so the same seed always gives the same choice
+        let mut names: Vec<&String> = presets
+            .keys()
+            .filter(|name| settings.disguise_presets.is_empty() || settings.disguise_presets.contains(name))
+            .collect();
+        names.sort();
+        let disguise_preset = names.choose(rng).map(|name| name.to_string());
+
+        Ok(Self {
+            voice_id: voice.id.clone(),
+            speaker_id,
+            prosody,
+            disguise_preset,
+        })
+    }
+}
+
+/// One line of the session log
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SessionLogEntry {
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    /// "start" for a new session, "rotate" for a scheduled change
+    pub reason: String,
+    pub identity: Identity,
+}
+
+/// A randomized identity in use, with what it replaced
+pub struct IdentitySession {
+    identity: Identity,
+    chosen_at: Instant,
+    rotate_every: Option<Duration>,
+    previous_voice: Option<(String, Option<u32>)>,
+    previous_disguise: DisguiseSettings,
+    previous_match_language: bool,
+}
+
+impl IdentitySession {
+    pub fn identity(&self) -> &Identity {
+        &self.identity
+    }
+}
+
+/// Picks and applies a random identity if the option is on. Called as the
+/// pipeline starts; failures leave the user's own voice in place.
+pub fn begin_session<R: Runtime>(state: &PipelineState, app: &AppHandle<R>) {
+    let settings = match Settings::load() {
+        Ok(settings) => settings,
+        Err(e) => {
+            log::error!("Failed to load identity settings: {}", e);
+            return;
+        }
+    };
+    if !settings.random_identity.enabled {
+        return;
+    }
+
+    let (previous_voice, previous_match_language) = {
+        let tts = state.tts.lock().unwrap();
+        (tts.selected_voice(), tts.match_language())
+    };
+    let previous_disguise = state.get_disguise();
+    match change_identity(state, &settings, "start") {
+        Ok(identity) => {
+            // Switching voices by language would give the user's own voice away
+            state.tts.lock().unwrap().set_match_language(false);
+            let _ = app.emit("identity-changed", &identity);
+            *state.identity.lock().unwrap() = Some(IdentitySession {
+                identity,
+                chosen_at: Instant::now(),
+                rotate_every: settings.random_identity.rotate_every(),
+                previous_voice,
+                previous_disguise,
+                previous_match_language,
+            });
+        }
+        Err(e) => log::error!("Failed to pick a random identity: {}", e),
+    }
+}
+
+/// Picks a new identity if the session's rotation interval has passed. Called
+/// by the TTS stage between replies, since the new voice's model is loaded
+/// before this returns.
+pub fn rotate_if_due<R: Runtime>(state: &PipelineState, app: &AppHandle<R>) {
+    let mut session = state.identity.lock().unwrap();
+    let session = match session.as_mut() {
+        Some(session) => session,
+        None => return,
+    };
+    match session.rotate_every {
+        Some(interval) if session.chosen_at.elapsed() >= interval => {}
+        _ => return,
+    }
+    // Try again next interval, whatever happens now
+    session.chosen_at = Instant::now();
+
+    let result = Settings::load().and_then(|settings| change_identity(state, &settings, "rotate"));
+    match result {
+        Ok(identity) => {
+            let _ = app.emit("identity-changed", &identity);
+            session.identity = identity;
+        }
+        Err(e) => log::error!("Failed to rotate identity: {}", e),
+    }
+}
+
+/// Puts back the voice and disguise the session's identity replaced
+pub fn end_session(state: &PipelineState) {
+    let session = match state.identity.lock().unwrap().take() {
+        Some(session) => session,
+        None => return,
+    };
+
+    let mut tts = state.tts.lock().unwrap();
+    tts.set_match_language(session.previous_match_language);
+    if let Err(e) = tts.set_prosody_override(None) {
+        log::error!("Failed to clear session prosody: {}", e);
+    }
+    if let Some((voice_id, speaker_id)) = &session.previous_voice {
+        if let Err(e) = tts.select_voice(voice_id, *speaker_id) {
+            log::error!("Failed to restore voice {}: {}", voice_id, e);
+        }
+    }
+    drop(tts);
+    if let Err(e) = state.set_disguise(session.previous_disguise) {
+        log::error!("Failed to restore disguise: {}", e);
+    }
+    log::info!("Session identity ended");
+}
+
+/// Draws an identity, applies it and records it in the session log
+fn change_identity(state: &PipelineState, settings: &Settings, reason: &str) -> Result<Identity> {
+    let identity = {
+        let mut tts = state.tts.lock().unwrap();
+        let identity = Identity::choose(
+            &settings.random_identity,
+            &tts.list_voices(),
+            &settings.disguise_presets,
+            settings.prosody,
+            &mut rand::thread_rng(),
+        )?;
+        tts.select_voice(&identity.voice_id, identity.speaker_id)?;
+        tts.set_prosody_override(Some(identity.prosody))?;
+        identity
+    };
+
+    let disguise = identity
+        .disguise_preset
+        .as_ref()
+        .and_then(|name| settings.disguise_presets.get(name))
+        .copied()
+        .unwrap_or_default();
+    state.set_disguise(disguise)?;
+
+    log::info!(
+        "Session identity: voice {} speaker {:?}, disguise {:?}",
+        identity.voice_id,
+        identity.speaker_id,
+        identity.disguise_preset
+    );
+    if let Err(e) = append_log(reason, &identity) {
+        log::error!("Failed to write session log: {}", e);
+    }
+    Ok(identity)
+}
+
+fn append_log(reason: &str, identity: &Identity) -> Result<()> {
+    let path = Settings::session_log_path().context("Could not determine config directory")?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let entry = SessionLogEntry {
+        timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+        reason: reason.to_string(),
+        identity: identity.clone(),
+    };
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open session log {:?}", path))?;
+    writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+    Ok(())
+}
+
+/// Reads the session log, oldest entry first. Unreadable lines are skipped.
+pub fn read_log() -> Result<Vec<SessionLogEntry>> {
+    let path = Settings::session_log_path().context("Could not determine config directory")?;
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let contents = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read session log {:?}", path))?;
+    Ok(contents
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
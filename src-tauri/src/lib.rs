@@ -1,17 +1,43 @@
+mod agc;
 mod audio;
+mod calibration;
+mod command_stt;
+mod command_tts;
+mod denoise;
+mod disguise;
+mod identity;
+mod models;
 mod pipeline;
+mod piper;
+mod playout;
 mod settings;
+#[cfg(feature = "silero-vad")]
+mod silero;
+mod stages;
+mod streaming;
 mod stt;
 mod tts;
+mod vad;
+mod voices;
 
-use pipeline::{run_pipeline, stop_pipeline, PipelineState};
+use disguise::DisguiseSettings;
+use models::ModelRegistry;
+use pipeline::{run_pipeline, stop_pipeline, InputMode, InputProcessing, PipelineState};
+use std::collections::HashMap;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::thread;
-use tauri::{AppHandle, State};
+use tauri::{AppHandle, Emitter, State};
+use voices::VoiceRegistry;
+
+// Public so the doctests on what the output buffer accepts can name it
+#[doc(hidden)]
+pub use audio::OutputBuffer;
 
 struct AppState {
     pipeline: Arc<PipelineState>,
+    voice_registry: Mutex<VoiceRegistry>,
+    model_registry: ModelRegistry,
 }
 
 #[tauri::command]
@@ -78,21 +104,138 @@ fn set_output_device(state: State<AppState>, name: String) -> Result<(), String>
 }
 
 #[tauri::command]
-fn load_whisper_model(state: State<AppState>, path: String) -> Result<(), String> {
+fn list_whisper_models(state: State<AppState>) -> Result<Vec<models::WhisperModel>, String> {
+    Ok(state.model_registry.scan())
+}
+
+#[tauri::command]
+fn get_whisper_model(state: State<AppState>) -> Result<Option<PathBuf>, String> {
+    let stt = state.pipeline.stt.lock().map_err(|e| e.to_string())?;
+    Ok(stt.whisper_model_path().map(|p| p.to_path_buf()))
+}
+
+/// Loads a Whisper model on a background thread. `path` also accepts a model
+/// id from `list_whisper_models`. The running pipeline keeps the old model
+/// until the new one is ready and then picks it up between utterances.
+/// Progress is reported as "whisper-model-status" events.
+#[tauri::command]
+fn load_whisper_model(app: AppHandle, state: State<AppState>, path: String) -> Result<(), String> {
+    let model_path = state
+        .model_registry
+        .resolve(&path)
+        .ok_or_else(|| format!("Whisper model not found: {}", path))?;
+
+    let generation = {
+        let mut stt = state.pipeline.stt.lock().map_err(|e| e.to_string())?;
+        stt.begin_model_load()
+    };
+
+    let pipeline = Arc::clone(&state.pipeline);
+    let _ = app.emit("whisper-model-status", "loading");
+    thread::spawn(move || {
+        // Load without holding the STT lock so transcription carries on meanwhile
+        let loaded = match stt::LoadedWhisperModel::load(model_path) {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                log::error!("Failed to load Whisper model: {}", e);
+                let _ = app.emit("whisper-model-error", e.to_string());
+                return;
+            }
+        };
+
+        let installed = match pipeline.stt.lock() {
+            Ok(mut stt) => stt.finish_model_load(generation, loaded),
+            Err(_) => false,
+        };
+        if !installed {
+            return;
+        }
+
+        if let Err(e) = settings::Settings::update(|s| s.whisper_model = Some(path)) {
+            log::error!("Failed to save Whisper model choice: {}", e);
+        }
+        let _ = app.emit("whisper-model-status", "ready");
+    });
+
+    Ok(())
+}
+
+#[tauri::command]
+fn get_stt_backend(state: State<AppState>) -> Result<stt::SttBackendKind, String> {
+    let stt = state.pipeline.stt.lock().map_err(|e| e.to_string())?;
+    Ok(stt.active_kind())
+}
+
+#[tauri::command]
+fn set_stt_backend(state: State<AppState>, backend: stt::SttBackendKind) -> Result<(), String> {
     let mut stt = state.pipeline.stt.lock().map_err(|e| e.to_string())?;
-    stt.load_model(PathBuf::from(path)).map_err(|e| e.to_string())
+    stt.set_active(backend).map_err(|e| e.to_string())?;
+
+    settings::Settings::update(|s| s.stt_backend = backend)
+        .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-fn list_voices(state: State<AppState>) -> Result<Vec<(String, String)>, String> {
+fn set_stt_command(
+    state: State<AppState>,
+    config: command_stt::CommandSttConfig,
+) -> Result<(), String> {
+    let mut stt = state.pipeline.stt.lock().map_err(|e| e.to_string())?;
+    stt.set_command(config.clone()).map_err(|e| e.to_string())?;
+
+    settings::Settings::update(|s| s.stt_command = Some(config))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_stt_options(state: State<AppState>) -> Result<stt::SttOptions, String> {
+    let stt = state.pipeline.stt.lock().map_err(|e| e.to_string())?;
+    Ok(stt.options().clone())
+}
+
+#[tauri::command]
+fn set_stt_options(state: State<AppState>, options: stt::SttOptions) -> Result<(), String> {
+    let mut stt = state.pipeline.stt.lock().map_err(|e| e.to_string())?;
+    stt.set_options(options.clone()).map_err(|e| e.to_string())?;
+
+    settings::Settings::update(|s| s.stt_options = options)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_match_voice_language(state: State<AppState>) -> Result<bool, String> {
+    let tts = state.pipeline.tts.lock().map_err(|e| e.to_string())?;
+    Ok(tts.match_language())
+}
+
+/// Turns on speaking each transcript with a voice for its language. Ignored
+/// while a random session identity is in use.
+#[tauri::command]
+fn set_match_voice_language(state: State<AppState>, enabled: bool) -> Result<(), String> {
+    if state.pipeline.identity.lock().map_err(|e| e.to_string())?.is_some() {
+        return Err("Voice language matching is off while a random identity is in use".to_string());
+    }
+    let mut tts = state.pipeline.tts.lock().map_err(|e| e.to_string())?;
+    tts.set_match_language(enabled);
+
+    settings::Settings::update(|s| s.match_voice_language = enabled)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn list_voices(state: State<AppState>) -> Result<Vec<tts::Voice>, String> {
     let tts = state.pipeline.tts.lock().map_err(|e| e.to_string())?;
     Ok(tts.list_voices())
 }
 
 #[tauri::command]
-fn select_voice(state: State<AppState>, voice_id: String) -> Result<(), String> {
+fn select_voice(
+    state: State<AppState>,
+    voice_id: String,
+    speaker_id: Option<u32>,
+) -> Result<(), String> {
     let mut tts = state.pipeline.tts.lock().map_err(|e| e.to_string())?;
-    tts.select_voice(&voice_id).map_err(|e| e.to_string())
+    tts.select_voice(&voice_id, speaker_id).map_err(|e| e.to_string())
 }
 
 #[tauri::command]
@@ -114,6 +257,82 @@ fn add_voice(
         .map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+fn add_command_voice(
+    state: State<AppState>,
+    config: command_tts::CommandVoiceConfig,
+) -> Result<(), String> {
+    let mut tts = state.pipeline.tts.lock().map_err(|e| e.to_string())?;
+    tts.add_command_voice(config.clone())
+        .map_err(|e| e.to_string())?;
+
+    settings::Settings::update(|s| s.command_voices.push(config))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn remove_command_voice(state: State<AppState>, voice_id: String) -> Result<(), String> {
+    let mut tts = state.pipeline.tts.lock().map_err(|e| e.to_string())?;
+    tts.remove_command_voice(&voice_id)
+        .map_err(|e| e.to_string())?;
+
+    settings::Settings::update(|s| s.command_voices.retain(|v| v.id != voice_id))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn rescan_voices(state: State<AppState>) -> Result<Vec<tts::Voice>, String> {
+    let mut registry = state.voice_registry.lock().map_err(|e| e.to_string())?;
+
+    // Pick up directories added to the settings file since startup
+    if let Ok(settings) = settings::Settings::load() {
+        for dir in settings.voice_dirs {
+            registry.add_dir(dir);
+        }
+    }
+
+    let voices = registry.scan();
+    let mut tts = state.pipeline.tts.lock().map_err(|e| e.to_string())?;
+    tts.set_voices(voices);
+    Ok(tts.list_voices())
+}
+
+#[tauri::command]
+fn get_prosody(state: State<AppState>, voice_id: Option<String>) -> Result<tts::Prosody, String> {
+    let tts = state.pipeline.tts.lock().map_err(|e| e.to_string())?;
+    Ok(tts.get_prosody(voice_id.as_deref()))
+}
+
+#[tauri::command]
+fn set_prosody(
+    state: State<AppState>,
+    voice_id: Option<String>,
+    prosody: tts::Prosody,
+) -> Result<(), String> {
+    let mut tts = state.pipeline.tts.lock().map_err(|e| e.to_string())?;
+    tts.set_prosody(voice_id.as_deref(), prosody)
+        .map_err(|e| e.to_string())?;
+
+    settings::Settings::update(|s| match voice_id {
+        Some(id) => {
+            s.voice_prosody.insert(id, prosody);
+        }
+        None => s.prosody = prosody,
+    })
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn clear_voice_prosody(state: State<AppState>, voice_id: String) -> Result<(), String> {
+    let mut tts = state.pipeline.tts.lock().map_err(|e| e.to_string())?;
+    tts.clear_voice_prosody(&voice_id);
+
+    settings::Settings::update(|s| {
+        s.voice_prosody.remove(&voice_id);
+    })
+    .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 fn get_silence_duration(state: State<AppState>) -> Result<u64, String> {
     Ok(state.pipeline.get_silence_duration_ms())
@@ -121,10 +340,294 @@ fn get_silence_duration(state: State<AppState>) -> Result<u64, String> {
 
 #[tauri::command]
 fn set_silence_duration(state: State<AppState>, ms: u64) -> Result<(), String> {
-    state.pipeline.set_silence_duration_ms(ms);
+    state.pipeline.set_silence_duration_ms(ms)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_vad_config(state: State<AppState>) -> Result<vad::VadConfig, String> {
+    Ok(state.pipeline.get_vad_config())
+}
+
+#[tauri::command]
+fn set_vad_config(state: State<AppState>, config: vad::VadConfig) -> Result<(), String> {
+    state.pipeline.set_vad_config(config)
+        .map_err(|e| e.to_string())?;
+
+    settings::Settings::update(|s| s.vad_config = config)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_streaming(state: State<AppState>) -> Result<bool, String> {
+    Ok(state.pipeline.is_streaming())
+}
+
+#[tauri::command]
+fn set_streaming(state: State<AppState>, enabled: bool) -> Result<(), String> {
+    state.pipeline.set_streaming(enabled);
+
+    settings::Settings::update(|s| s.streaming = enabled)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_vad_settings(state: State<AppState>) -> Result<vad::VadSettings, String> {
+    Ok(state.pipeline.get_vad_settings())
+}
+
+#[tauri::command]
+fn set_vad_settings(state: State<AppState>, settings: vad::VadSettings) -> Result<(), String> {
+    state.pipeline.set_vad_settings(settings)
+        .map_err(|e| e.to_string())?;
+
+    settings::Settings::update(|s| s.vad = settings)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn list_vad_detectors() -> Result<Vec<vad::VadKind>, String> {
+    Ok(vad::available_detectors())
+}
+
+#[tauri::command]
+fn get_input_mode(state: State<AppState>) -> Result<InputMode, String> {
+    Ok(state.pipeline.input_mode())
+}
+
+#[tauri::command]
+fn set_input_mode(state: State<AppState>, mode: InputMode) -> Result<(), String> {
+    state.pipeline.set_input_mode(mode);
+
+    settings::Settings::update(|s| s.input_mode = mode)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn ptt_press(state: State<AppState>) -> Result<(), String> {
+    state.pipeline.ptt_press();
     Ok(())
 }
 
+#[tauri::command]
+fn ptt_release(state: State<AppState>) -> Result<(), String> {
+    state.pipeline.ptt_release();
+    Ok(())
+}
+
+#[tauri::command]
+fn is_muted(state: State<AppState>) -> Result<bool, String> {
+    Ok(state.pipeline.is_muted())
+}
+
+#[tauri::command]
+fn set_mute(state: State<AppState>, muted: bool) -> Result<(), String> {
+    state.pipeline.set_muted(muted);
+    Ok(())
+}
+
+#[tauri::command]
+fn get_input_gain(state: State<AppState>) -> Result<f32, String> {
+    Ok(state.pipeline.get_input_gain())
+}
+
+#[tauri::command]
+fn set_input_gain(state: State<AppState>, gain: f32) -> Result<(), String> {
+    state.pipeline.set_input_gain(gain)
+        .map_err(|e| e.to_string())?;
+
+    settings::Settings::update(|s| s.input_gain = gain)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_input_processing(state: State<AppState>) -> Result<InputProcessing, String> {
+    let device = {
+        let manager = state.pipeline.audio_manager.lock().map_err(|e| e.to_string())?;
+        manager.get_input_device_name().map_err(|e| e.to_string())?
+    };
+    Ok(state.pipeline.get_input_processing(&device))
+}
+
+/// Sets the input processing for the selected input device. A running
+/// pipeline picks it up right away.
+#[tauri::command]
+fn set_input_processing(state: State<AppState>, processing: InputProcessing) -> Result<(), String> {
+    let device = {
+        let manager = state.pipeline.audio_manager.lock().map_err(|e| e.to_string())?;
+        manager.get_input_device_name().map_err(|e| e.to_string())?
+    };
+    state.pipeline.set_input_processing(&device, processing)
+        .map_err(|e| e.to_string())?;
+
+    settings::Settings::update(|s| {
+        s.input_processing.insert(device, processing);
+    })
+    .map_err(|e| e.to_string())
+}
+
+/// Calibrates the selected microphone on a background thread: records the
+/// room, then the user talking, and applies the resulting input gain and VAD
+/// threshold. Progress is reported as "calibration-status" events and the
+/// outcome as "calibration-result" or "calibration-error".
+#[tauri::command]
+fn calibrate_input(app: AppHandle, state: State<AppState>) -> Result<(), String> {
+    if state.pipeline.is_running() {
+        return Err("Stop the pipeline before calibrating".to_string());
+    }
+
+    let pipeline = Arc::clone(&state.pipeline);
+    thread::spawn(move || {
+        let calibration = match calibration::calibrate(&pipeline.audio_manager, &app) {
+            Ok(calibration) => calibration,
+            Err(e) => {
+                log::error!("Calibration failed: {}", e);
+                let _ = app.emit("calibration-error", e.to_string());
+                return;
+            }
+        };
+
+        let mut vad = pipeline.get_vad_settings();
+        calibration.apply_to(&mut vad);
+        let applied = pipeline.set_input_gain(calibration.input_gain)
+            .and_then(|_| pipeline.set_vad_settings(vad))
+            .and_then(|_| settings::Settings::update(|s| {
+                s.input_gain = calibration.input_gain;
+                s.vad = vad;
+                s.calibration = Some(calibration);
+            }));
+        if let Err(e) = applied {
+            log::error!("Failed to apply calibration: {}", e);
+            let _ = app.emit("calibration-error", e.to_string());
+            return;
+        }
+
+        let _ = app.emit("calibration-status", "done");
+        let _ = app.emit("calibration-result", calibration);
+    });
+
+    Ok(())
+}
+
+#[tauri::command]
+fn get_disguise(state: State<AppState>) -> Result<DisguiseSettings, String> {
+    Ok(state.pipeline.get_disguise())
+}
+
+#[tauri::command]
+fn list_disguise_presets() -> Result<HashMap<String, DisguiseSettings>, String> {
+    let settings = settings::Settings::load().map_err(|e| e.to_string())?;
+    Ok(settings.disguise_presets)
+}
+
+#[tauri::command]
+fn get_disguise_preset() -> Result<Option<String>, String> {
+    let settings = settings::Settings::load().map_err(|e| e.to_string())?;
+    Ok(settings.disguise_preset)
+}
+
+/// Saves a named disguise preset, replacing any preset with the same name.
+/// If it's the active preset the change is heard from the next sentence.
+#[tauri::command]
+fn save_disguise_preset(
+    state: State<AppState>,
+    name: String,
+    disguise: DisguiseSettings,
+) -> Result<(), String> {
+    if name.trim().is_empty() {
+        return Err("Preset name cannot be empty".to_string());
+    }
+    disguise.validate().map_err(|e| e.to_string())?;
+
+    let mut active = false;
+    settings::Settings::update(|s| {
+        active = s.disguise_preset.as_deref() == Some(name.as_str());
+        s.disguise_presets.insert(name, disguise);
+    })
+    .map_err(|e| e.to_string())?;
+
+    if active {
+        state.pipeline.set_disguise(disguise).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Deletes a disguise preset. Deleting the active preset turns the disguise off.
+#[tauri::command]
+fn delete_disguise_preset(state: State<AppState>, name: String) -> Result<(), String> {
+    let mut active = false;
+    settings::Settings::update(|s| {
+        s.disguise_presets.remove(&name);
+        if s.disguise_preset.as_deref() == Some(name.as_str()) {
+            s.disguise_preset = None;
+            active = true;
+        }
+    })
+    .map_err(|e| e.to_string())?;
+
+    if active {
+        state.pipeline.set_disguise(DisguiseSettings::default()).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Applies a saved disguise preset to synthesized speech, or turns the
+/// disguise off when `name` is `None`
+#[tauri::command]
+fn select_disguise_preset(state: State<AppState>, name: Option<String>) -> Result<(), String> {
+    let settings = settings::Settings::load().map_err(|e| e.to_string())?;
+    let disguise = match &name {
+        Some(name) => *settings.disguise_presets.get(name)
+            .ok_or_else(|| format!("Unknown disguise preset: {}", name))?,
+        None => DisguiseSettings::default(),
+    };
+    state.pipeline.set_disguise(disguise).map_err(|e| e.to_string())?;
+
+    settings::Settings::update(|s| s.disguise_preset = name)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_playout_timing(state: State<AppState>) -> Result<playout::PlayoutTiming, String> {
+    Ok(state.pipeline.get_playout_timing())
+}
+
+#[tauri::command]
+fn set_playout_timing(state: State<AppState>, timing: playout::PlayoutTiming) -> Result<(), String> {
+    state.pipeline.set_playout_timing(timing)
+        .map_err(|e| e.to_string())?;
+
+    settings::Settings::update(|s| s.playout_timing = timing)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_random_identity() -> Result<identity::IdentitySettings, String> {
+    let settings = settings::Settings::load().map_err(|e| e.to_string())?;
+    Ok(settings.random_identity)
+}
+
+/// Saves the random identity options. They take effect the next time the
+/// pipeline starts.
+#[tauri::command]
+fn set_random_identity(identity: identity::IdentitySettings) -> Result<(), String> {
+    identity.validate().map_err(|e| e.to_string())?;
+    settings::Settings::update(|s| s.random_identity = identity)
+        .map_err(|e| e.to_string())
+}
+
+/// The identity the running session speaks with, if it was randomized
+#[tauri::command]
+fn get_session_identity(state: State<AppState>) -> Result<Option<identity::Identity>, String> {
+    let session = state.pipeline.identity.lock().map_err(|e| e.to_string())?;
+    Ok(session.as_ref().map(|s| s.identity().clone()))
+}
+
+#[tauri::command]
+fn get_session_log() -> Result<Vec<identity::SessionLogEntry>, String> {
+    identity::read_log().map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 fn load_settings() -> Result<settings::Settings, String> {
     settings::Settings::load().map_err(|e| e.to_string())
@@ -138,6 +641,24 @@ fn save_settings(settings: settings::Settings) -> Result<(), String> {
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     let pipeline = Arc::new(PipelineState::new().expect("Failed to create pipeline"));
+    let voice_registry;
+    let model_registry;
+
+    let saved_settings = match settings::Settings::load() {
+        Ok(mut settings) => {
+            if settings.seed_disguise_preset(&mut rand::thread_rng()) {
+                log::info!("Generated a personal disguise preset");
+                if let Err(e) = settings.save() {
+                    log::error!("Failed to save the personal disguise preset: {}", e);
+                }
+            }
+            settings
+        }
+        Err(e) => {
+            log::error!("Failed to load settings: {}", e);
+            settings::Settings::new()
+        }
+    };
 
     // Auto-load models on startup
     {
@@ -161,13 +682,18 @@ pub fn run() {
 
         log::info!("Looking for resources in: {:?}", possible_dirs);
 
-        // Load Whisper model (tiny model - fastest)
-        let whisper_model = possible_dirs.iter()
-            .flat_map(|d| vec![
-                d.join("ggml-tiny.en.bin"),                    // Production: flat in resources
-                d.join("models").join("ggml-tiny.en.bin"),    // Dev: in models folder
-            ])
-            .find(|p| p.exists());
+        // Discover Whisper models (production: flat in resources, dev: in models folder)
+        let mut model_dirs: Vec<PathBuf> = possible_dirs.iter()
+            .flat_map(|d| vec![d.clone(), d.join("models")])
+            .collect();
+        model_dirs.extend(settings::Settings::user_models_dir());
+        model_registry = ModelRegistry::new(model_dirs);
+
+        // Load the saved model, falling back to tiny.en (fastest) or whatever is installed
+        let whisper_model = saved_settings.whisper_model.as_deref()
+            .and_then(|id| model_registry.resolve(id))
+            .or_else(|| model_registry.resolve("ggml-tiny.en.bin"))
+            .or_else(|| model_registry.scan().into_iter().next().map(|m| m.path));
 
         if let Some(model_path) = whisper_model {
             log::info!("Loading Whisper model from: {:?}", model_path);
@@ -182,6 +708,51 @@ pub fn run() {
             log::warn!("Whisper model not found");
         }
 
+        pipeline.set_streaming(saved_settings.streaming);
+        if let Err(e) = pipeline.set_vad_config(saved_settings.vad_config) {
+            log::error!("Invalid saved segmentation settings: {}", e);
+        }
+        pipeline.set_input_mode(saved_settings.input_mode);
+        if let Err(e) = pipeline.set_input_gain(saved_settings.input_gain) {
+            log::error!("Invalid saved input gain: {}", e);
+        }
+        for (device, processing) in &saved_settings.input_processing {
+            if let Err(e) = pipeline.set_input_processing(device, *processing) {
+                log::error!("Invalid saved input processing for {}: {}", device, e);
+            }
+        }
+        if let Err(e) = pipeline.set_vad_settings(saved_settings.vad) {
+            log::error!("Invalid saved VAD settings: {}", e);
+        }
+        if let Err(e) = pipeline.set_playout_timing(saved_settings.playout_timing) {
+            log::error!("Invalid saved playout timing: {}", e);
+        }
+        if let Some(name) = &saved_settings.disguise_preset {
+            match saved_settings.disguise_presets.get(name) {
+                Some(disguise) => {
+                    if let Err(e) = pipeline.set_disguise(*disguise) {
+                        log::error!("Invalid saved disguise preset {}: {}", name, e);
+                    }
+                }
+                None => log::warn!("Saved disguise preset not found: {}", name),
+            }
+        }
+
+        // Restore the STT backend and language settings
+        if let Ok(mut stt) = pipeline.stt.lock() {
+            if let Some(config) = saved_settings.stt_command.clone() {
+                if let Err(e) = stt.set_command(config) {
+                    log::error!("Invalid STT command: {}", e);
+                }
+            }
+            if let Err(e) = stt.set_active(saved_settings.stt_backend) {
+                log::error!("Failed to select STT backend: {}", e);
+            }
+            if let Err(e) = stt.set_options(saved_settings.stt_options.clone()) {
+                log::error!("Invalid STT language settings: {}", e);
+            }
+        }
+
         // Configure Piper TTS
         let piper_exe = possible_dirs.iter()
             .flat_map(|d| vec![
@@ -192,66 +763,79 @@ pub fn run() {
 
         log::info!("Piper exe search result: {:?}", piper_exe);
 
-        if let Some(piper_path) = piper_exe {
-            log::info!("Configuring Piper TTS from: {:?}", piper_path);
-            if let Ok(mut tts) = pipeline.tts.lock() {
+        // Optional Silero VAD model, run on the ONNX Runtime that ships with Piper
+        let silero_model = possible_dirs.iter()
+            .chain(settings::Settings::user_models_dir().iter())
+            .flat_map(|d| vec![
+                d.join("silero_vad.onnx"),
+                d.join("models").join("silero_vad.onnx"),
+            ])
+            .find(|p| p.exists());
+
+        #[cfg(feature = "silero-vad")]
+        if silero_model.is_some() {
+            let mut runtime_dirs = possible_dirs.clone();
+            runtime_dirs.extend(piper_exe.iter().filter_map(|p| p.parent().map(|p| p.to_path_buf())));
+            if let Err(e) = silero::init_runtime(&runtime_dirs) {
+                log::error!("{}", e);
+            }
+        }
+
+        log::info!("Silero VAD model search result: {:?}", silero_model);
+        pipeline.set_silero_model(silero_model);
+
+        // Discover voices (production: in voices/ subfolder, dev: in models/voices)
+        let mut voice_dirs: Vec<PathBuf> = possible_dirs.iter()
+            .flat_map(|d| vec![
+                d.join("voices"),
+                d.join("models").join("voices"),
+            ])
+            .collect();
+        voice_dirs.extend(settings::Settings::user_voices_dir());
+        voice_dirs.extend(saved_settings.voice_dirs.iter().cloned());
+        voice_registry = VoiceRegistry::new(voice_dirs);
+
+        if let Ok(mut tts) = pipeline.tts.lock() {
+            tts.set_voices(voice_registry.scan());
+            tts.set_command_voices(saved_settings.command_voices.clone());
+            tts.set_match_language(saved_settings.match_voice_language);
+
+            // Apply saved prosody before the first worker starts
+            if let Err(e) = tts.set_prosody(None, saved_settings.prosody) {
+                log::error!("Invalid saved prosody: {}", e);
+            }
+            for (voice_id, prosody) in &saved_settings.voice_prosody {
+                if let Err(e) = tts.set_prosody(Some(voice_id), *prosody) {
+                    log::error!("Invalid saved prosody for {}: {}", voice_id, e);
+                }
+            }
+
+            if let Some(piper_path) = piper_exe {
+                log::info!("Configuring Piper TTS from: {:?}", piper_path);
                 if let Err(e) = tts.set_piper_path(piper_path) {
                     log::error!("Failed to set Piper path: {}", e);
-                } else {
-                    // Define available voices
-                    let voices = [
-                        ("lessac", "Lessac (Neutral)", "en_US-lessac-medium"),
-                        ("ryan", "Ryan (Male)", "en_US-ryan-medium"),
-                        ("alba", "Alba (Female)", "en_GB-alba-medium"),
-                    ];
-
-                    let mut first_voice = None;
-                    for (id, name, file_base) in voices {
-                        // Find voice files (production: in voices/ subfolder, dev: in models/voices)
-                        let model_path = possible_dirs.iter()
-                            .flat_map(|d| vec![
-                                d.join("voices").join(format!("{}.onnx", file_base)),  // Production
-                                d.join("models").join("voices").join(format!("{}.onnx", file_base)),  // Dev
-                            ])
-                            .find(|p| p.exists());
-                        let config_path = possible_dirs.iter()
-                            .flat_map(|d| vec![
-                                d.join("voices").join(format!("{}.onnx.json", file_base)),  // Production
-                                d.join("models").join("voices").join(format!("{}.onnx.json", file_base)),  // Dev
-                            ])
-                            .find(|p| p.exists());
-
-                        if let (Some(model), Some(config)) = (model_path, config_path) {
-                            if let Err(e) = tts.add_voice(id, name, model, config) {
-                                log::error!("Failed to add voice {}: {}", id, e);
-                            } else {
-                                log::info!("Added voice: {} ({})", name, id);
-                                if first_voice.is_none() {
-                                    first_voice = Some(id);
-                                }
-                            }
-                        } else {
-                            log::warn!("Voice files not found for: {}", id);
-                        }
-                    }
+                }
+            } else {
+                log::warn!("Piper executable not found");
+            }
 
-                    // Select the first available voice
-                    if let Some(voice_id) = first_voice {
-                        if let Err(e) = tts.select_voice(voice_id) {
-                            log::error!("Failed to select voice: {}", e);
-                        } else {
-                            log::info!("Selected default voice: {}", voice_id);
-                        }
-                    }
+            // Select the first available voice
+            if let Some(voice) = tts.list_voices().first() {
+                if let Err(e) = tts.select_voice(&voice.id, None) {
+                    log::error!("Failed to select voice: {}", e);
+                } else {
+                    log::info!("Selected default voice: {}", voice.id);
                 }
             }
-        } else {
-            log::warn!("Piper executable not found");
         }
     }
 
     tauri::Builder::default()
-        .manage(AppState { pipeline })
+        .manage(AppState {
+            pipeline,
+            voice_registry: Mutex::new(voice_registry),
+            model_registry,
+        })
         .setup(|app| {
             if cfg!(debug_assertions) {
                 app.handle().plugin(
@@ -272,13 +856,58 @@ pub fn run() {
             get_default_output_device,
             set_input_device,
             set_output_device,
+            list_whisper_models,
+            get_whisper_model,
             load_whisper_model,
+            get_stt_backend,
+            set_stt_backend,
+            set_stt_command,
+            get_stt_options,
+            set_stt_options,
+            get_match_voice_language,
+            set_match_voice_language,
             list_voices,
             select_voice,
             set_piper_path,
             add_voice,
+            add_command_voice,
+            remove_command_voice,
+            rescan_voices,
+            get_prosody,
+            set_prosody,
+            clear_voice_prosody,
             get_silence_duration,
             set_silence_duration,
+            get_vad_config,
+            set_vad_config,
+            get_streaming,
+            set_streaming,
+            get_vad_settings,
+            set_vad_settings,
+            list_vad_detectors,
+            get_input_mode,
+            set_input_mode,
+            ptt_press,
+            ptt_release,
+            is_muted,
+            set_mute,
+            get_input_gain,
+            set_input_gain,
+            get_input_processing,
+            set_input_processing,
+            calibrate_input,
+            get_disguise,
+            list_disguise_presets,
+            get_disguise_preset,
+            save_disguise_preset,
+            delete_disguise_preset,
+            select_disguise_preset,
+            get_playout_timing,
+            set_playout_timing,
+            get_random_identity,
+            set_random_identity,
+            get_session_identity,
+            get_session_log,
             load_settings,
             save_settings,
         ])
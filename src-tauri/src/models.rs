@@ -0,0 +1,116 @@
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Model sizes in order of speed, fastest first
+const SIZE_ORDER: [&str; 5] = ["tiny", "base", "small", "medium", "large"];
+
+/// A Whisper ggml model file found on disk
+#[derive(Clone, Debug, Serialize)]
+pub struct WhisperModel {
+    /// File name, e.g. "ggml-base.en-q5_1.bin"
+    pub id: String,
+    pub path: PathBuf,
+    /// Model size, e.g. "tiny", "small" or "large-v3"
+    pub size: String,
+    /// `.en` models only understand English but are more accurate at it
+    pub english_only: bool,
+    /// Quantization such as "q5_1", or `None` for full precision
+    pub quantization: Option<String>,
+    pub file_size: u64,
+}
+
+impl WhisperModel {
+    /// Parses a whisper.cpp model file name (`ggml-<size>[.en][-<quant>].bin`)
+    fn from_path(path: &Path) -> Option<Self> {
+        let id = path.file_name()?.to_str()?.to_string();
+        let name = id.strip_prefix("ggml-")?.strip_suffix(".bin")?;
+
+        let (name, quantization) = match name.rsplit_once('-') {
+            Some((rest, quant)) if is_quantization(quant) => (rest, Some(quant.to_string())),
+            _ => (name, None),
+        };
+        let (size, english_only) = match name.strip_suffix(".en") {
+            Some(size) => (size, true),
+            None => (name, false),
+        };
+
+        Some(Self {
+            id: id.clone(),
+            path: path.to_path_buf(),
+            size: size.to_string(),
+            english_only,
+            quantization,
+            file_size: fs::metadata(path).map(|m| m.len()).unwrap_or(0),
+        })
+    }
+
+    fn size_rank(&self) -> usize {
+        SIZE_ORDER
+            .iter()
+            .position(|s| self.size.starts_with(s))
+            .unwrap_or(SIZE_ORDER.len())
+    }
+}
+
+/// Quantization suffixes look like "q5_0", "q5_1" or "q8_0"
+fn is_quantization(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars.next() == Some('q') && chars.next().map(|c| c.is_ascii_digit()).unwrap_or(false)
+}
+
+/// Discovers Whisper models by scanning directories for `ggml-*.bin` files
+pub struct ModelRegistry {
+    dirs: Vec<PathBuf>,
+}
+
+impl ModelRegistry {
+    pub fn new(dirs: Vec<PathBuf>) -> Self {
+        let mut unique: Vec<PathBuf> = Vec::new();
+        for dir in dirs {
+            if !unique.contains(&dir) {
+                unique.push(dir);
+            }
+        }
+        Self { dirs: unique }
+    }
+
+    /// Scans all directories, fastest models first. When the same file exists
+    /// in several directories, the one in the earliest directory wins.
+    pub fn scan(&self) -> Vec<WhisperModel> {
+        let mut models: Vec<WhisperModel> = Vec::new();
+
+        for dir in &self.dirs {
+            let entries = match fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+
+            for path in entries.filter_map(|e| e.ok().map(|e| e.path())) {
+                if let Some(model) = WhisperModel::from_path(&path) {
+                    if !models.iter().any(|m| m.id == model.id) {
+                        models.push(model);
+                    }
+                }
+            }
+        }
+
+        models.sort_by(|a, b| {
+            a.size_rank()
+                .cmp(&b.size_rank())
+                .then_with(|| a.size.cmp(&b.size))
+                .then_with(|| b.english_only.cmp(&a.english_only))
+                .then_with(|| a.quantization.cmp(&b.quantization))
+        });
+        models
+    }
+
+    /// Finds a model by id, or treats `id` as a path to a model file
+    pub fn resolve(&self, id: &str) -> Option<PathBuf> {
+        self.scan()
+            .into_iter()
+            .find(|m| m.id == id)
+            .map(|m| m.path)
+            .or_else(|| Some(PathBuf::from(id)).filter(|p| p.exists()))
+    }
+}
@@ -1,33 +1,156 @@
-use crate::audio::{create_input_stream, create_output_stream, AudioManager};
+use crate::agc::{AgcSettings, AutomaticGainControl};
+use crate::audio::{
+    create_input_stream, create_output_stream, extend_scrubbed, scrub, scrub_ring, AudioManager, OutputBuffer,
+};
+use crate::calibration::validate_gain;
+use crate::denoise::NoiseSuppressor;
+use crate::disguise::DisguiseSettings;
+use crate::identity::{self, IdentitySession};
+use crate::playout::PlayoutTiming;
+use crate::stages::{spawn_stages, SttJob};
 use crate::stt::SpeechToText;
 use crate::tts::TextToSpeech;
+use crate::vad::{VadConfig, VadSettings, VoiceActivityDetector, MAX_ROLL_MS};
 use anyhow::Result;
 use cpal::traits::DeviceTrait;
-use rubato::{Resampler, SincFixedIn, SincInterpolationType, SincInterpolationParameters, WindowFunction};
-use std::collections::VecDeque;
-use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
-use tauri::{AppHandle, Emitter};
+use tauri::{AppHandle, Emitter, Runtime};
 
-const SILENCE_THRESHOLD: f32 = 0.01;
-const MIN_SPEECH_DURATION_MS: u64 = 300;   // Process quickly - even short words like "hey"
-const DEFAULT_SILENCE_DURATION_MS: u64 = 700;  // Default pause detection time
 const DEBUG_AUDIO_INTERVAL_MS: u64 = 1000; // Log audio levels every second
-const PRE_ROLL_MS: u64 = 250;  // Capture audio from before speech is detected
-const POST_ROLL_MS: u64 = 200; // Keep recording after speech ends to capture word endings
+const CUT_SEARCH_MS: u64 = 2000; // How far back to look for a quiet spot to cut at
+const CUT_FRAME_MS: u64 = 20; // Resolution of the quiet-spot search
+const STREAM_INTERVAL_MS: u64 = 500; // How often to re-transcribe in streaming mode
+const STREAM_MIN_WINDOW_MS: u64 = 1000; // Don't bother transcribing less than this
+const LEVEL_INTERVAL_MS: u64 = 100; // How often the UI meter is updated
+
+/// When the microphone is listened to
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InputMode {
+    /// Always listening; voice activity detection finds the utterances
+    #[default]
+    Vad,
+    /// Listening only while the push-to-talk key is held
+    PushToTalk,
+    /// Each press of the key starts or stops listening
+    Toggle,
+}
+
+impl InputMode {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => InputMode::PushToTalk,
+            2 => InputMode::Toggle,
+            _ => InputMode::Vad,
+        }
+    }
+}
+
+/// Processing applied to one input device's signal before voice activity detection
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InputProcessing {
+    /// Spectral noise suppression
+    pub noise_suppression: bool,
+    /// Automatic gain control and limiter, after noise suppression
+    pub agc: AgcSettings,
+}
+
+impl InputProcessing {
+    pub fn validate(&self) -> Result<()> {
+        self.agc.validate()
+    }
+}
+
+/// Payload of the `audio-level` event
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct AudioLevel {
+    pub rms: f32,
+    pub peak: f32,
+    pub noise_floor: f32,
+    pub is_speech: bool,
+}
+
+/// Input levels collected by the input callback between two `audio-level` events
+#[derive(Default)]
+struct LevelMeter {
+    sum_squares: f32,
+    samples: usize,
+    peak: f32,
+    noise_floor: f32,
+    is_speech: bool,
+}
+
+impl LevelMeter {
+    fn add(&mut self, samples: &[f32], noise_floor: f32, is_speech: bool) {
+        for sample in samples {
+            self.sum_squares += sample * sample;
+            self.peak = self.peak.max(sample.abs());
+        }
+        self.samples += samples.len();
+        self.noise_floor = noise_floor;
+        self.is_speech = is_speech;
+    }
+
+    /// Returns the level since the last call and starts over
+    fn take(&mut self) -> Option<AudioLevel> {
+        if self.samples == 0 {
+            return None;
+        }
+
+        let level = AudioLevel {
+            rms: (self.sum_squares / self.samples as f32).sqrt(),
+            peak: self.peak,
+            noise_floor: self.noise_floor,
+            is_speech: self.is_speech,
+        };
+        self.sum_squares = 0.0;
+        self.samples = 0;
+        self.peak = 0.0;
+        Some(level)
+    }
+}
 
 /// Thread-safe state that can be shared with Tauri
 pub struct PipelineState {
     pub audio_manager: Mutex<AudioManager>,
     pub stt: Mutex<SpeechToText>,
     pub tts: Mutex<TextToSpeech>,
+    // Random identity for the running session, if the option is on
+    pub identity: Mutex<Option<IdentitySession>>,
     is_running: AtomicBool,
     // Channel to signal stop
     stop_signal: Mutex<Option<Arc<AtomicBool>>>,
-    // Configurable silence duration (ms)
-    silence_duration_ms: std::sync::atomic::AtomicU64,
+    // Utterance segmentation, picked up live by the input callback and segmenter
+    vad_config: Mutex<VadConfig>,
+    // Transcribe and speak while the user is still talking
+    streaming: AtomicBool,
+    // Voice activity detector tuning, picked up live by the input callback
+    vad_settings: Mutex<VadSettings>,
+    // Applied to the microphone before anything else, picked up live
+    input_gain: Mutex<f32>,
+    // Per-device input processing, keyed by device name
+    input_processing: Mutex<HashMap<String, InputProcessing>>,
+    // Voice disguise applied to synthesized speech, picked up per sentence
+    disguise: Mutex<DisguiseSettings>,
+    // When synthesized speech may start playing, picked up per sentence
+    playout_timing: Mutex<PlayoutTiming>,
+    // Silero VAD model, loaded when the pipeline starts
+    silero_model: Mutex<Option<PathBuf>>,
+    // Input gating: mode, key state and mute. They are read by the input
+    // callback, so all three are atomics; the mode is an `InputMode` as u8.
+    input_mode: AtomicU8,
+    key_active: AtomicBool,
+    muted: AtomicBool,
+    // Set when the gate closes, so the segmenter ends the utterance right away
+    cut_requested: AtomicBool,
+    discard_requested: AtomicBool,
 }
 
 impl PipelineState {
@@ -36,9 +159,22 @@ impl PipelineState {
             audio_manager: Mutex::new(AudioManager::new()?),
             stt: Mutex::new(SpeechToText::new()),
             tts: Mutex::new(TextToSpeech::new()),
+            identity: Mutex::new(None),
             is_running: AtomicBool::new(false),
             stop_signal: Mutex::new(None),
-            silence_duration_ms: AtomicU64::new(DEFAULT_SILENCE_DURATION_MS),
+            vad_config: Mutex::new(VadConfig::default()),
+            streaming: AtomicBool::new(false),
+            vad_settings: Mutex::new(VadSettings::default()),
+            input_gain: Mutex::new(1.0),
+            input_processing: Mutex::new(HashMap::new()),
+            disguise: Mutex::new(DisguiseSettings::default()),
+            playout_timing: Mutex::new(PlayoutTiming::default()),
+            silero_model: Mutex::new(None),
+            input_mode: AtomicU8::new(InputMode::default() as u8),
+            key_active: AtomicBool::new(false),
+            muted: AtomicBool::new(false),
+            cut_requested: AtomicBool::new(false),
+            discard_requested: AtomicBool::new(false),
         })
     }
 
@@ -47,51 +183,172 @@ impl PipelineState {
     }
 
     pub fn get_silence_duration_ms(&self) -> u64 {
-        self.silence_duration_ms.load(Ordering::SeqCst)
+        self.get_vad_config().silence_duration_ms
     }
 
-    pub fn set_silence_duration_ms(&self, ms: u64) {
-        self.silence_duration_ms.store(ms, Ordering::SeqCst);
+    pub fn set_silence_duration_ms(&self, ms: u64) -> Result<()> {
+        let config = VadConfig {
+            silence_duration_ms: ms,
+            ..self.get_vad_config()
+        };
+        self.set_vad_config(config)
     }
-}
 
-unsafe impl Send for PipelineState {}
-unsafe impl Sync for PipelineState {}
+    pub fn get_vad_config(&self) -> VadConfig {
+        *self.vad_config.lock().unwrap()
+    }
 
-/// Resamples audio from one sample rate to another
-fn resample_audio(input: &[f32], from_rate: u32, to_rate: u32) -> Result<Vec<f32>> {
-    if from_rate == to_rate {
-        return Ok(input.to_vec());
+    pub fn set_vad_config(&self, config: VadConfig) -> Result<()> {
+        config.validate()?;
+        *self.vad_config.lock().unwrap() = config;
+        Ok(())
     }
 
-    let params = SincInterpolationParameters {
-        sinc_len: 256,
-        f_cutoff: 0.95,
-        interpolation: SincInterpolationType::Linear,
-        oversampling_factor: 256,
-        window: WindowFunction::BlackmanHarris2,
-    };
+    pub fn is_streaming(&self) -> bool {
+        self.streaming.load(Ordering::SeqCst)
+    }
 
-    let ratio = to_rate as f64 / from_rate as f64;
-    let mut resampler = SincFixedIn::<f32>::new(
-        ratio,
-        2.0, // max relative ratio
-        params,
-        input.len(),
-        1, // mono
-    )?;
+    pub fn set_streaming(&self, enabled: bool) {
+        self.streaming.store(enabled, Ordering::SeqCst);
+    }
+
+    pub fn get_vad_settings(&self) -> VadSettings {
+        *self.vad_settings.lock().unwrap()
+    }
+
+    pub fn set_vad_settings(&self, settings: VadSettings) -> Result<()> {
+        settings.validate()?;
+        *self.vad_settings.lock().unwrap() = settings;
+        Ok(())
+    }
+
+    pub fn get_input_gain(&self) -> f32 {
+        *self.input_gain.lock().unwrap()
+    }
+
+    pub fn set_input_gain(&self, gain: f32) -> Result<()> {
+        validate_gain(gain)?;
+        *self.input_gain.lock().unwrap() = gain;
+        Ok(())
+    }
+
+    pub fn get_input_processing(&self, device: &str) -> InputProcessing {
+        self.input_processing.lock().unwrap().get(device).copied().unwrap_or_default()
+    }
+
+    pub fn set_input_processing(&self, device: &str, processing: InputProcessing) -> Result<()> {
+        processing.validate()?;
+        self.input_processing.lock().unwrap().insert(device.to_string(), processing);
+        Ok(())
+    }
+
+    pub fn get_disguise(&self) -> DisguiseSettings {
+        *self.disguise.lock().unwrap()
+    }
+
+    pub fn set_disguise(&self, settings: DisguiseSettings) -> Result<()> {
+        settings.validate()?;
+        *self.disguise.lock().unwrap() = settings;
+        Ok(())
+    }
+
+    pub fn get_playout_timing(&self) -> PlayoutTiming {
+        *self.playout_timing.lock().unwrap()
+    }
+
+    pub fn set_playout_timing(&self, timing: PlayoutTiming) -> Result<()> {
+        timing.validate()?;
+        *self.playout_timing.lock().unwrap() = timing;
+        Ok(())
+    }
+
+    pub fn set_silero_model(&self, path: Option<PathBuf>) {
+        *self.silero_model.lock().unwrap() = path;
+    }
+
+    pub fn input_mode(&self) -> InputMode {
+        InputMode::from_u8(self.input_mode.load(Ordering::SeqCst))
+    }
+
+    pub fn set_input_mode(&self, mode: InputMode) {
+        self.input_mode.store(mode as u8, Ordering::SeqCst);
+        if self.key_active.swap(false, Ordering::SeqCst) {
+            self.cut_requested.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Push-to-talk key pressed: opens the input, or flips it in toggle mode
+    pub fn ptt_press(&self) {
+        match self.input_mode() {
+            InputMode::Vad => {}
+            InputMode::PushToTalk => self.key_active.store(true, Ordering::SeqCst),
+            InputMode::Toggle => {
+                let was_active = self.key_active.fetch_xor(true, Ordering::SeqCst);
+                if was_active {
+                    self.cut_requested.store(true, Ordering::SeqCst);
+                }
+            }
+        }
+    }
+
+    /// Push-to-talk key released: closes the input and ends the utterance immediately
+    pub fn ptt_release(&self) {
+        if self.input_mode() == InputMode::PushToTalk
+            && self.key_active.swap(false, Ordering::SeqCst)
+        {
+            self.cut_requested.store(true, Ordering::SeqCst);
+        }
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted.load(Ordering::SeqCst)
+    }
 
-    let waves_in = vec![input.to_vec()];
-    let waves_out = resampler.process(&waves_in, None)?;
+    /// Muting closes the input in every mode and throws away the utterance in progress
+    pub fn set_muted(&self, muted: bool) {
+        if !self.muted.swap(muted, Ordering::SeqCst) && muted {
+            self.discard_requested.store(true, Ordering::SeqCst);
+        }
+    }
 
-    Ok(waves_out.into_iter().next().unwrap_or_default())
+    /// Whether microphone audio is let through right now
+    fn input_open(&self) -> bool {
+        if self.is_muted() {
+            return false;
+        }
+        match self.input_mode() {
+            InputMode::Vad => true,
+            InputMode::PushToTalk | InputMode::Toggle => self.key_active.load(Ordering::SeqCst),
+        }
+    }
 }
 
+unsafe impl Send for PipelineState {}
+unsafe impl Sync for PipelineState {}
+
 /// Helper to emit status events
-fn emit_status(app: &AppHandle, status: &str) {
+pub fn emit_status<R: Runtime>(app: &AppHandle<R>, status: &str) {
     let _ = app.emit("pipeline-status", status);
 }
 
+/// Returns the index of the middle of the quietest frame among the last
+/// `search_len` samples of `audio`
+fn quietest_point(audio: &[f32], search_len: usize, frame_len: usize) -> usize {
+    let start = audio.len().saturating_sub(search_len);
+    let frame_len = frame_len.max(1);
+
+    audio[start..]
+        .chunks(frame_len)
+        .enumerate()
+        .map(|(i, frame)| {
+            let energy = frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32;
+            (start + i * frame_len + frame.len() / 2, energy)
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(index, _)| index)
+        .unwrap_or(audio.len())
+}
+
 /// Runs the audio pipeline. This function blocks and should be run in a separate thread.
 /// The streams are kept alive within this function to avoid Send/Sync issues.
 pub fn run_pipeline(state: Arc<PipelineState>, app: AppHandle) -> Result<()> {
@@ -133,17 +390,24 @@ pub fn run_pipeline(state: Arc<PipelineState>, app: AppHandle) -> Result<()> {
 
     // Shared buffers
     let audio_input_buffer: Arc<Mutex<Vec<f32>>> = Arc::new(Mutex::new(Vec::new()));
-    let audio_output_buffer: Arc<Mutex<VecDeque<f32>>> = Arc::new(Mutex::new(VecDeque::new()));
+    // Only synthesized speech can be queued here, never microphone audio
+    let audio_output_buffer = Arc::new(OutputBuffer::new());
+
+    // Converts a duration in ms to a number of input samples
+    let samples_for = move |ms: u64| (input_sample_rate as u64 * ms / 1000) as usize;
 
-    // Pre-roll buffer: keeps recent audio to capture word beginnings
-    // Size = sample_rate * PRE_ROLL_MS / 1000
-    let pre_roll_size = (input_sample_rate as u64 * PRE_ROLL_MS / 1000) as usize;
-    let pre_roll_buffer: Arc<Mutex<VecDeque<f32>>> = Arc::new(Mutex::new(VecDeque::with_capacity(pre_roll_size)));
+    // Pre-roll buffer: keeps recent audio to capture word beginnings. Sized for
+    // the longest pre-roll plus an input block, so it never reallocates and
+    // leaves audio behind.
+    let mut config = state.get_vad_config();
+    let pre_roll_buffer: Arc<Mutex<VecDeque<f32>>> =
+        Arc::new(Mutex::new(VecDeque::with_capacity(samples_for(MAX_ROLL_MS * 2))));
 
     // Voice activity detection state
     let speech_start: Arc<Mutex<Option<Instant>>> = Arc::new(Mutex::new(None));
     let last_voice_activity: Arc<Mutex<Option<Instant>>> = Arc::new(Mutex::new(None));
     let last_debug_log: Arc<Mutex<Instant>> = Arc::new(Mutex::new(Instant::now()));
+    let level_meter: Arc<Mutex<LevelMeter>> = Arc::new(Mutex::new(LevelMeter::default()));
 
     // Clone for input callback
     let input_buffer_clone = Arc::clone(&audio_input_buffer);
@@ -151,31 +415,100 @@ pub fn run_pipeline(state: Arc<PipelineState>, app: AppHandle) -> Result<()> {
     let speech_start_clone = Arc::clone(&speech_start);
     let last_activity_clone = Arc::clone(&last_voice_activity);
     let last_debug_clone = Arc::clone(&last_debug_log);
+    let level_clone = Arc::clone(&level_meter);
     let stop_clone = Arc::clone(&stop_signal);
+    let state_clone = Arc::clone(&state);
+    let mut input_gain = state.get_input_gain();
+    let input_device_name = input_device.name().unwrap_or_default();
+    let mut processing = state.get_input_processing(&input_device_name);
+    let mut suppressor = NoiseSuppressor::new(input_sample_rate);
+    let mut agc = AutomaticGainControl::new(processing.agc, input_sample_rate);
+    let mut detector = VoiceActivityDetector::new(state.get_vad_settings(), input_sample_rate);
+    if let Some(model_path) = state.silero_model.lock().unwrap().as_deref() {
+        detector.load_silero(model_path);
+    }
 
     // Create input stream
-    let _input_stream = create_input_stream(
+    let input_stream = create_input_stream(
         &input_device,
         &input_config,
         sample_format,
-        move |data: Vec<f32>| {
+        move |mut data: Vec<f32>| {
             if stop_clone.load(Ordering::SeqCst) {
+                scrub(&mut data);
                 return;
             }
 
-            // Convert to mono if stereo
-            let mono_data: Vec<f32> = if input_channels > 1 {
-                data.chunks(input_channels as usize)
+            // Convert to mono if stereo. Every copy of the block is wiped once
+            // it has been used.
+            let mut mono_data: Vec<f32> = if input_channels > 1 {
+                let mono = data
+                    .chunks(input_channels as usize)
                     .map(|chunk| chunk.iter().sum::<f32>() / input_channels as f32)
-                    .collect()
+                    .collect();
+                scrub(&mut data);
+                mono
             } else {
                 data
             };
 
-            // Detect voice activity
-            let rms: f32 =
-                (mono_data.iter().map(|s| s * s).sum::<f32>() / mono_data.len() as f32).sqrt();
-            let is_speech = rms > SILENCE_THRESHOLD;
+            if let Ok(gain) = state_clone.input_gain.try_lock() {
+                input_gain = *gain;
+            }
+            if let Ok(latest) = state_clone.vad_config.try_lock() {
+                config = *latest;
+            }
+            if input_gain != 1.0 {
+                mono_data.iter_mut().for_each(|s| *s *= input_gain);
+            }
+
+            // Denoise and level the signal ahead of the VAD. This runs even while
+            // gated so the noise estimate and gain are current when the gate opens
+            if let Ok(devices) = state_clone.input_processing.try_lock() {
+                let latest = devices.get(&input_device_name).copied().unwrap_or_default();
+                if latest.noise_suppression && !processing.noise_suppression {
+                    log::info!("Noise suppression on, adding {} samples of latency", suppressor.latency());
+                    suppressor.reset();
+                }
+                if latest.agc.enabled && !processing.agc.enabled {
+                    agc.reset();
+                }
+                agc.set_settings(latest.agc);
+                processing = latest;
+            }
+            if processing.noise_suppression {
+                let denoised = suppressor.process(&mono_data);
+                scrub(&mut mono_data);
+                mono_data = denoised;
+            }
+            if processing.agc.enabled {
+                agc.process(&mut mono_data);
+            }
+
+            // While the input is gated nothing is recorded, and audio from before
+            // the gate opened is forgotten so it can't leak into the next utterance
+            if !state_clone.input_open() {
+                let mut pre_roll = pre_roll_clone.lock().unwrap();
+                if !pre_roll.is_empty() {
+                    scrub_ring(&mut pre_roll);
+                }
+                drop(pre_roll);
+                level_clone.lock().unwrap().add(&mono_data, detector.noise_floor(), false);
+                scrub(&mut mono_data);
+                return;
+            }
+
+            // Detect voice activity, picking up settings changes without blocking the callback
+            if let Ok(settings) = state_clone.vad_settings.try_lock() {
+                detector.set_settings(*settings);
+            }
+            let detected = detector.process(&mono_data);
+            // With a key held everything is speech; the key marks the utterance
+            let is_speech = match state_clone.input_mode() {
+                InputMode::Vad => detected,
+                InputMode::PushToTalk | InputMode::Toggle => true,
+            };
+            level_clone.lock().unwrap().add(&mono_data, detector.noise_floor(), is_speech);
 
             let now = Instant::now();
 
@@ -183,7 +516,17 @@ pub fn run_pipeline(state: Arc<PipelineState>, app: AppHandle) -> Result<()> {
             {
                 let mut last_log = last_debug_clone.lock().unwrap();
                 if now.duration_since(*last_log) >= Duration::from_millis(DEBUG_AUDIO_INTERVAL_MS) {
-                    log::info!("Audio RMS: {:.4}, threshold: {:.4}, speech: {}", rms, SILENCE_THRESHOLD, is_speech);
+                    let rms: f32 =
+                        (mono_data.iter().map(|s| s * s).sum::<f32>() / mono_data.len() as f32).sqrt();
+                    log::info!(
+                        "Audio RMS: {:.4}, noise floor: {:.4}, open: {:.4}, close: {:.4}, speech: {}",
+                        rms,
+                        detector.noise_floor(),
+                        detector.open_threshold(),
+                        detector.close_threshold(),
+                        is_speech
+                    );
+
                     *last_log = now;
                 }
             }
@@ -208,18 +551,20 @@ pub fn run_pipeline(state: Arc<PipelineState>, app: AppHandle) -> Result<()> {
                 if is_new_speech {
                     let pre_roll = pre_roll_clone.lock().unwrap();
                     log::info!("Prepending {} samples from pre-roll buffer", pre_roll.len());
-                    input_buf.extend(pre_roll.iter());
+                    let (front, back) = pre_roll.as_slices();
+                    extend_scrubbed(&mut input_buf, front);
+                    extend_scrubbed(&mut input_buf, back);
                 }
 
-                input_buf.extend_from_slice(&mono_data);
+                extend_scrubbed(&mut input_buf, &mono_data);
             } else if speech_active {
                 // Not speech, but we're in an active recording session
-                // Keep recording for POST_ROLL_MS after last voice activity
+                // Keep recording for the post-roll after last voice activity
                 let last_activity = last_activity_clone.lock().unwrap();
                 if let Some(last) = *last_activity {
-                    if now.duration_since(last) < Duration::from_millis(POST_ROLL_MS) {
+                    if now.duration_since(last) < Duration::from_millis(config.post_roll_ms) {
                         // Still within post-roll window, keep recording
-                        input_buffer_clone.lock().unwrap().extend_from_slice(&mono_data);
+                        extend_scrubbed(&mut input_buffer_clone.lock().unwrap(), &mono_data);
                     }
                 }
             }
@@ -227,13 +572,11 @@ pub fn run_pipeline(state: Arc<PipelineState>, app: AppHandle) -> Result<()> {
             // Always update pre-roll buffer (circular buffer of recent audio)
             {
                 let mut pre_roll = pre_roll_clone.lock().unwrap();
-                for sample in &mono_data {
-                    if pre_roll.len() >= pre_roll_size {
-                        pre_roll.pop_front();
-                    }
-                    pre_roll.push_back(*sample);
-                }
+                pre_roll.extend(mono_data.iter());
+                let excess = pre_roll.len().saturating_sub(samples_for(config.pre_roll_ms));
+                pre_roll.drain(..excess);
             }
+            scrub(&mut mono_data);
         },
     )?;
 
@@ -246,24 +589,113 @@ pub fn run_pipeline(state: Arc<PipelineState>, app: AppHandle) -> Result<()> {
 
     log::info!("Audio streams started");
 
-    // Processing loop
+    identity::begin_session(&state, &app);
+
+    let stages = spawn_stages(
+        Arc::clone(&state),
+        app.clone(),
+        Arc::clone(&stop_signal),
+        input_sample_rate,
+        output_sample_rate,
+        Arc::clone(&audio_output_buffer),
+    );
+
+    let cut_search_samples = samples_for(CUT_SEARCH_MS);
+    let cut_frame_samples = samples_for(CUT_FRAME_MS);
+
+    let mut last_level_event = Instant::now();
+    let mut last_vad_state = false;
+
+    let mut last_stream_pass = Instant::now();
+    let stream_min_samples = samples_for(STREAM_MIN_WINDOW_MS);
+
+    // VAD segmenter: cuts the input into utterances and hands them to the STT
+    // stage, so listening carries on while earlier speech is being processed
     while !stop_signal.load(Ordering::SeqCst) {
         thread::sleep(Duration::from_millis(50));
 
+        // Live input level for the UI meter, plus speech start/stop as it happens
+        if last_level_event.elapsed() >= Duration::from_millis(LEVEL_INTERVAL_MS) {
+            last_level_event = Instant::now();
+            if let Some(level) = level_meter.lock().unwrap().take() {
+                let _ = app.emit("audio-level", level);
+                if level.is_speech != last_vad_state {
+                    last_vad_state = level.is_speech;
+                    let _ = app.emit("vad-state", if level.is_speech { "speech" } else { "silence" });
+                }
+            }
+        }
+
+        let discard = state.discard_requested.swap(false, Ordering::SeqCst);
+        let cut = state.cut_requested.swap(false, Ordering::SeqCst);
+
         let now = Instant::now();
+        let config = state.get_vad_config();
+        let min_speech = Duration::from_millis(config.min_speech_ms);
+
+        // Muting drops the utterance in progress, and so does closing the gate
+        // on something too short to be speech, like a tap of the key
+        let too_short = cut
+            && speech_start
+                .lock()
+                .unwrap()
+                .is_some_and(|start| now.duration_since(start) < min_speech);
+        if discard || too_short {
+            {
+                let mut buf = audio_input_buffer.lock().unwrap();
+                scrub(&mut buf);
+                buf.clear();
+            }
+            *speech_start.lock().unwrap() = None;
+            *last_voice_activity.lock().unwrap() = None;
+            continue;
+        }
+
         let should_process = {
             let last_activity = last_voice_activity.lock().unwrap();
             let speech_start_val = speech_start.lock().unwrap();
 
-            if let (Some(last), Some(start)) = (*last_activity, *speech_start_val) {
-                let silence_ms = state.get_silence_duration_ms();
-                now.duration_since(last) >= Duration::from_millis(silence_ms)
-                    && now.duration_since(start) >= Duration::from_millis(MIN_SPEECH_DURATION_MS)
+            if cut {
+                // The gate closed: end the utterance now rather than waiting for silence
+                speech_start_val.is_some()
+            } else if let (Some(last), Some(start)) = (*last_activity, *speech_start_val) {
+                now.duration_since(last) >= Duration::from_millis(config.silence_duration_ms)
+                    && now.duration_since(start) >= min_speech
             } else {
                 false
             }
         };
 
+        // Someone talking without a pause: send what we have so far, cut at
+        // the quietest recent spot, and keep recording into the next segment
+        if !should_process && audio_input_buffer.lock().unwrap().len() >= samples_for(config.max_segment_ms) {
+            let segment = {
+                let mut buf = audio_input_buffer.lock().unwrap();
+                // Only search the second half, so every cut makes real progress
+                let search = cut_search_samples.min(buf.len() / 2);
+                let cut = quietest_point(&buf, search, cut_frame_samples);
+
+                // The cut is mid-speech, so there is no pause to pad with pre- or
+                // post-roll: overlapping the segments would speak those words twice
+                let segment = buf[..cut].to_vec();
+                // Move what's kept to a fresh buffer so the old one can be wiped
+                let rest = buf[cut..].to_vec();
+                scrub(&mut buf);
+                *buf = rest;
+                segment
+            };
+
+            log::info!("Utterance reached the maximum length, sending {} samples", segment.len());
+            let job = SttJob::Final {
+                audio: segment,
+                heard_at: Instant::now(),
+            };
+            if stages.stt_sender.send(job).is_err() {
+                break;
+            }
+            continue;
+        }
+
         if should_process {
             // Get audio buffer
             let buffer: Vec<f32> = {
@@ -275,69 +707,43 @@ pub fn run_pipeline(state: Arc<PipelineState>, app: AppHandle) -> Result<()> {
             *speech_start.lock().unwrap() = None;
             *last_voice_activity.lock().unwrap() = None;
 
+            // Blocks only when several utterances are already waiting for STT
             if !buffer.is_empty() {
-                log::info!("Processing {} samples", buffer.len());
-                emit_status(&app, "processing");
-
-                // Transcribe
-                let text = {
-                    let stt = state.stt.lock().unwrap();
-                    if stt.is_loaded() {
-                        stt.transcribe(&buffer, input_sample_rate).ok()
-                    } else {
-                        log::warn!("Whisper model not loaded");
-                        None
-                    }
+                let job = SttJob::Final {
+                    audio: buffer,
+                    heard_at: now,
                 };
-
-                if let Some(text) = text {
-                    let text = text.trim();
-                    // Filter out blank audio markers and very short/noisy transcriptions
-                    if !text.is_empty() && !text.contains("[BLANK_AUDIO]") && text.len() > 1 {
-                        log::info!("Transcribed: {}", text);
-                        emit_status(&app, "speaking");
-
-                        // Synthesize
-                        let (audio, tts_sample_rate) = {
-                            let tts = state.tts.lock().unwrap();
-                            if tts.is_ready() {
-                                let rate = tts.get_sample_rate();
-                                (tts.synthesize(&text).ok(), rate)
-                            } else {
-                                log::warn!("TTS not ready");
-                                (None, 22050)
-                            }
-                        };
-
-                        if let Some(audio) = audio {
-                            log::info!("Synthesized {} samples at {} Hz", audio.len(), tts_sample_rate);
-
-                            // Resample TTS output to match output device sample rate
-                            let resampled = if tts_sample_rate != output_sample_rate {
-                                log::info!("Resampling from {} Hz to {} Hz", tts_sample_rate, output_sample_rate);
-                                match resample_audio(&audio, tts_sample_rate, output_sample_rate) {
-                                    Ok(data) => data,
-                                    Err(e) => {
-                                        log::error!("Resampling failed: {}", e);
-                                        audio
-                                    }
-                                }
-                            } else {
-                                audio
-                            };
-
-                            log::info!("Output {} samples to playback buffer", resampled.len());
-                            let mut out = audio_output_buffer.lock().unwrap();
-                            out.extend(resampled.into_iter());
-                        }
-                    }
+                if stages.stt_sender.send(job).is_err() {
+                    break;
                 }
-
-                emit_status(&app, "listening");
+            }
+        } else if state.is_streaming()
+            && speech_start.lock().unwrap().is_some()
+            && now.duration_since(last_stream_pass) >= Duration::from_millis(STREAM_INTERVAL_MS)
+        {
+            last_stream_pass = now;
+
+            let window = {
+                let buf = audio_input_buffer.lock().unwrap();
+                (buf.len() >= stream_min_samples).then(|| buf.clone())
+            };
+            if let Some(window) = window {
+                // Partial passes are best effort; skip this one if STT is behind
+                let _ = stages.stt_sender.try_send(SttJob::Partial {
+                    audio: window,
+                    heard_at: now,
+                });
             }
         }
     }
 
+    stages.shutdown();
+    identity::end_session(&state);
+    // Stop capturing before wiping what was captured
+    drop(input_stream);
+    scrub(&mut audio_input_buffer.lock().unwrap());
+    scrub_ring(&mut pre_roll_buffer.lock().unwrap());
+
     log::info!("Pipeline stopped");
     emit_status(&app, "stopped");
     state.is_running.store(false, Ordering::SeqCst);
@@ -0,0 +1,592 @@
+use crate::tts::{BackendKind, Prosody, Speaker, SynthesisOptions, TtsBackend, Voice};
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(windows)]
+use std::os::windows::process::CommandExt;
+
+/// Piper outputs at 22050 Hz unless the voice config says otherwise
+const DEFAULT_SAMPLE_RATE: u32 = 22050;
+
+/// How long one utterance may take before the worker is treated as hung
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Once Piper reports an utterance done, its audio is already in the pipe;
+/// stdout going quiet this long means all of it has been read
+const DRAIN_QUIET: Duration = Duration::from_millis(50);
+/// Piper builds that don't log finished utterances are framed by stdout
+/// staying quiet this long instead, once audio has started
+const END_QUIET: Duration = Duration::from_secs(1);
+/// Workers kept running: the voice in use and the most recent ones before it.
+/// Each holds a loaded model, so the rest are stopped.
+const MAX_WORKERS: usize = 3;
+
+/// A Piper voice: its metadata plus the model files it is loaded from
+#[derive(Clone, Debug)]
+pub struct PiperVoice {
+    pub voice: Voice,
+    pub model_path: PathBuf,
+    pub config_path: PathBuf,
+}
+
+impl PiperVoice {
+    /// Builds a voice from a Piper model and its config, reading the metadata from the config
+    pub fn from_files(id: &str, name: &str, model_path: PathBuf, config_path: PathBuf) -> Result<Self> {
+        if !model_path.exists() {
+            return Err(anyhow!("Voice model not found: {:?}", model_path));
+        }
+        if !config_path.exists() {
+            return Err(anyhow!("Voice config not found: {:?}", config_path));
+        }
+
+        let config = VoiceConfig::load(&config_path)?;
+        let speakers = config.speakers();
+
+        Ok(Self {
+            voice: Voice {
+                id: id.to_string(),
+                name: name.to_string(),
+                backend: BackendKind::Piper,
+                sample_rate: config.audio.sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE),
+                language: config.language.and_then(|l| l.code),
+                quality: config.audio.quality,
+                num_speakers: config.num_speakers,
+                speakers,
+            },
+            model_path,
+            config_path,
+        })
+    }
+}
+
+/// The subset of a Piper `.onnx.json` voice config we care about
+#[derive(Debug, Default, Deserialize)]
+pub struct VoiceConfig {
+    #[serde(default)]
+    pub dataset: Option<String>,
+    #[serde(default)]
+    pub audio: AudioConfig,
+    #[serde(default)]
+    pub language: Option<LanguageConfig>,
+    #[serde(default = "default_num_speakers")]
+    pub num_speakers: u32,
+    #[serde(default)]
+    pub speaker_id_map: HashMap<String, u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AudioConfig {
+    pub sample_rate: Option<u32>,
+    pub quality: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LanguageConfig {
+    pub code: Option<String>,
+}
+
+fn default_num_speakers() -> u32 {
+    1
+}
+
+impl VoiceConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read voice config {:?}", path))?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse voice config {:?}", path))
+    }
+
+    /// Lists the speakers of a multi-speaker model in id order. Speakers missing
+    /// from `speaker_id_map` get a generic name.
+    pub fn speakers(&self) -> Vec<Speaker> {
+        if self.num_speakers <= 1 {
+            return Vec::new();
+        }
+
+        let mut names: HashMap<u32, &str> = HashMap::new();
+        for (name, id) in &self.speaker_id_map {
+            names.entry(*id).or_insert(name);
+        }
+
+        (0..self.num_speakers)
+            .map(|id| Speaker {
+                id,
+                name: names
+                    .get(&id)
+                    .map(|n| n.to_string())
+                    .unwrap_or_else(|| format!("Speaker {}", id)),
+            })
+            .collect()
+    }
+}
+
+/// TTS backend running Piper voices on persistent worker processes, one per
+/// recently used voice, so switching back and forth doesn't reload models
+pub struct PiperBackend {
+    piper_path: Option<PathBuf>,
+    voices: Vec<PiperVoice>,
+    /// Workers by voice id, least recently used first
+    workers: Vec<(String, PiperWorker)>,
+}
+
+impl PiperBackend {
+    pub fn new() -> Self {
+        Self {
+            piper_path: None,
+            voices: Vec::new(),
+            workers: Vec::new(),
+        }
+    }
+
+    pub fn set_piper_path(&mut self, path: PathBuf) -> Result<()> {
+        if !path.exists() {
+            return Err(anyhow!("Piper executable not found: {:?}", path));
+        }
+        self.piper_path = Some(path);
+        self.workers.clear();
+        Ok(())
+    }
+
+    pub fn add_voice(&mut self, voice: PiperVoice) {
+        self.voices.push(voice);
+    }
+
+    pub fn set_voices(&mut self, voices: Vec<PiperVoice>) {
+        self.workers.retain(|(id, _)| voices.iter().any(|v| v.voice.id == *id));
+        self.voices = voices;
+    }
+
+    /// Returns the running worker for a voice, spawning a new one if there is
+    /// none, it was started from another model or prosody, or it has exited.
+    fn worker(&mut self, voice_id: &str, prosody: Prosody) -> Result<&mut PiperWorker> {
+        let piper_path = self
+            .piper_path
+            .as_ref()
+            .ok_or_else(|| anyhow!("Piper path not set"))?;
+
+        let voice = self
+            .voices
+            .iter()
+            .find(|v| v.voice.id == voice_id)
+            .ok_or_else(|| anyhow!("Voice not found: {}", voice_id))?;
+
+        let existing = self.workers.iter().position(|(id, _)| id == voice_id);
+        let reusable = match existing {
+            Some(index) => {
+                let worker = &mut self.workers[index].1;
+                worker.model_path() == voice.model_path
+                    && worker.prosody() == prosody
+                    && worker.is_alive()
+            }
+            None => false,
+        };
+
+        if reusable {
+            // Move it to the most recently used end
+            let entry = self.workers.remove(existing.unwrap());
+            self.workers.push(entry);
+        } else {
+            // Drop old workers first so their processes are gone before the new one loads
+            self.workers.retain(|(id, _)| id != voice_id);
+            while self.workers.len() >= MAX_WORKERS {
+                self.workers.remove(0);
+            }
+            let worker = PiperWorker::spawn(piper_path, &voice.model_path, &voice.config_path, prosody)?;
+            self.workers.push((voice_id.to_string(), worker));
+        }
+
+        Ok(&mut self.workers.last_mut().unwrap().1)
+    }
+
+    fn remove_worker(&mut self, voice_id: &str) {
+        self.workers.retain(|(id, _)| id != voice_id);
+    }
+}
+
+impl Default for PiperBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TtsBackend for PiperBackend {
+    fn list_voices(&self) -> Vec<Voice> {
+        self.voices.iter().map(|v| v.voice.clone()).collect()
+    }
+
+    fn sample_rate(&self, voice_id: &str) -> Option<u32> {
+        self.voices
+            .iter()
+            .find(|v| v.voice.id == voice_id)
+            .map(|v| v.voice.sample_rate)
+    }
+
+    fn synthesize(&mut self, voice_id: &str, text: &str, options: &SynthesisOptions) -> Result<Vec<f32>> {
+        let result = match self.worker(voice_id, options.prosody)?.synthesize(text, options.speaker_id) {
+            Ok(samples) => Ok(samples),
+            Err(e) => {
+                // The worker may have crashed or hung mid-request; retry once on a fresh process
+                log::warn!("Piper worker failed ({}), restarting", e);
+                self.remove_worker(voice_id);
+                self.worker(voice_id, options.prosody)?
+                    .synthesize(text, options.speaker_id)
+            }
+        };
+        if result.is_err() {
+            self.remove_worker(voice_id);
+        }
+        result
+    }
+
+    fn is_available(&self) -> bool {
+        self.piper_path.is_some()
+    }
+
+    fn prepare(&mut self, voice_id: &str, options: &SynthesisOptions) -> Result<()> {
+        self.worker(voice_id, options.prosody).map(|_| ())
+    }
+}
+
+/// A long-lived Piper process with one voice model loaded.
+///
+/// Requests are written to stdin as one JSON object per line and Piper streams
+/// the raw 16-bit PCM of each back on stdout. The stream itself has no
+/// boundaries, so an utterance ends when Piper logs that it has finished it,
+/// or failing that, when stdout goes quiet. The logged audio length isn't
+/// used, since builds differ in what it covers. Nothing touches the disk.
+pub struct PiperWorker {
+    child: Child,
+    stdin: ChildStdin,
+    /// PCM chunks and finished utterances, in the order they were read
+    output: Receiver<PiperOutput>,
+    model_path: PathBuf,
+    prosody: Prosody,
+    timeout: Duration,
+}
+
+/// What the reader threads pick up from Piper
+enum PiperOutput {
+    Pcm(Vec<u8>),
+    /// Piper logged that it finished an utterance
+    Done,
+}
+
+impl PiperWorker {
+    /// Starts Piper with the voice loaded. Prosody is fixed for the lifetime of
+    /// the process, so changing it means spawning a new worker.
+    pub fn spawn(piper_path: &Path, model_path: &Path, config_path: &Path, prosody: Prosody) -> Result<Self> {
+        let mut cmd = Command::new(piper_path);
+        cmd.arg("--model")
+            .arg(model_path)
+            .arg("--config")
+            .arg(config_path)
+            .arg("--length_scale")
+            .arg(prosody.length_scale.to_string())
+            .arg("--noise_scale")
+            .arg(prosody.noise_scale.to_string())
+            .arg("--noise_w")
+            .arg(prosody.noise_w.to_string())
+            .arg("--sentence_silence")
+            .arg(prosody.sentence_silence.to_string())
+            .arg("--json-input")
+            .arg("--output_raw")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        // Hide console window on Windows
+        #[cfg(windows)]
+        cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
+
+        let mut child = cmd.spawn()
+            .map_err(|e| anyhow!("Failed to spawn piper: {}", e))?;
+
+        let stdin = child.stdin.take().ok_or_else(|| anyhow!("Piper stdin unavailable"))?;
+        let mut stdout = child.stdout.take().ok_or_else(|| anyhow!("Piper stdout unavailable"))?;
+        let stderr = child.stderr.take().ok_or_else(|| anyhow!("Piper stderr unavailable"))?;
+
+        // Both pipes are read on their own threads so a silent Piper can't block
+        // a read, and requests can time out instead
+        let (output_tx, output) = mpsc::channel();
+        let pcm_tx = output_tx.clone();
+        thread::spawn(move || {
+            let mut buf = [0u8; 8192];
+            loop {
+                match stdout.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        if pcm_tx.send(PiperOutput::Pcm(buf[..n].to_vec())).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+
+        thread::spawn(move || {
+            for line in BufReader::new(stderr).lines().map_while(|l| l.ok()) {
+                log::debug!("piper: {}", line);
+                if reports_utterance_done(&line) && output_tx.send(PiperOutput::Done).is_err() {
+                    break;
+                }
+            }
+        });
+
+        log::info!("Started Piper worker (pid {}) for {:?}", child.id(), model_path);
+
+        Ok(Self {
+            child,
+            stdin,
+            output,
+            model_path: model_path.to_path_buf(),
+            prosody,
+            timeout: RESPONSE_TIMEOUT,
+        })
+    }
+
+    pub fn model_path(&self) -> &Path {
+        &self.model_path
+    }
+
+    pub fn prosody(&self) -> Prosody {
+        self.prosody
+    }
+
+    /// Returns false once the child process has exited.
+    pub fn is_alive(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(None))
+    }
+
+    /// Synthesizes one utterance and returns its samples. `speaker_id` picks the
+    /// speaker of a multi-speaker model. After an error the worker's process
+    /// may be hung or gone, so it shouldn't be used again.
+    pub fn synthesize(&mut self, text: &str, speaker_id: Option<u32>) -> Result<Vec<f32>> {
+        // Whatever is left of an earlier utterance that was cut short belongs
+        // to no request, and keeping it would put every later one out of step
+        while self.output.try_recv().is_ok() {}
+
+        let mut request = serde_json::json!({ "text": text });
+        if let Some(speaker_id) = speaker_id {
+            request["speaker_id"] = speaker_id.into();
+        }
+        writeln!(self.stdin, "{}", request)
+            .map_err(|e| anyhow!("Failed to write to Piper: {}", e))?;
+        self.stdin.flush()?;
+
+        let deadline = Instant::now() + self.timeout;
+        let mut bytes = Vec::new();
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(self.timed_out());
+            }
+            // Until audio starts, Piper may still be loading or inferring
+            let wait = if bytes.is_empty() {
+                deadline - now
+            } else {
+                END_QUIET.min(deadline - now)
+            };
+            match self.output.recv_timeout(wait) {
+                Ok(PiperOutput::Pcm(chunk)) => bytes.extend_from_slice(&chunk),
+                Ok(PiperOutput::Done) => {
+                    // The stdout reader may still be catching up with the pipe
+                    while let Ok(output) = self.output.recv_timeout(DRAIN_QUIET) {
+                        if let PiperOutput::Pcm(chunk) = output {
+                            bytes.extend_from_slice(&chunk);
+                        }
+                    }
+                    break;
+                }
+                Err(RecvTimeoutError::Timeout) if bytes.is_empty() => return Err(self.timed_out()),
+                Err(RecvTimeoutError::Timeout) => {
+                    log::debug!("Piper went quiet without reporting the utterance done");
+                    break;
+                }
+                Err(RecvTimeoutError::Disconnected) => return Err(anyhow!("Piper exited unexpectedly")),
+            }
+        }
+
+        Ok(bytes
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0)
+            .collect())
+    }
+
+    fn timed_out(&self) -> anyhow::Error {
+        anyhow!("Piper did not respond within {} s", self.timeout.as_secs())
+    }
+}
+
+impl Drop for PiperWorker {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        log::info!("Stopped Piper worker for {:?}", self.model_path);
+    }
+}
+
+/// Whether a line of Piper's log marks the end of an utterance, e.g.
+/// `[piper] [info] Real-time factor: 0.05 (infer=0.1 sec, audio=2.04 sec)`
+fn reports_utterance_done(line: &str) -> bool {
+    line.contains("Real-time factor")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Per request, a stand-in for Piper writes `n * 200` samples of the value
+    /// `n * 257` for the nth request, so frames can be told apart
+    const WRITE_AUDIO: &str = r#"head -c $((n * 400)) /dev/zero | tr '\000' "\00$n""#;
+    /// Piper's completion line, with an audio length that is deliberately wrong
+    const LOG_DONE: &str = r#"echo "[piper] [info] Real-time factor: 0.1 (infer=0.01 sec, audio=$n sec)" >&2"#;
+
+    /// Writes an executable stand-in for Piper that runs `body` for every
+    /// request line, with `n` counting the requests
+    #[cfg(unix)]
+    fn fake_piper(name: &str, body: &str) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("parrot-{}-{}", name, std::process::id()));
+        let script = format!("#!/bin/sh\nn=0\nwhile read -r line; do\nn=$((n + 1))\n{}\ndone\n", body);
+        fs::write(&path, script).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[cfg(unix)]
+    fn spawn_fake(piper: &Path) -> PiperWorker {
+        PiperWorker::spawn(piper, Path::new("voice.onnx"), Path::new("voice.onnx.json"), Prosody::default()).unwrap()
+    }
+
+    fn test_voice(id: &str) -> PiperVoice {
+        PiperVoice {
+            voice: Voice {
+                id: id.to_string(),
+                name: id.to_string(),
+                backend: BackendKind::Piper,
+                sample_rate: DEFAULT_SAMPLE_RATE,
+                language: None,
+                quality: None,
+                num_speakers: 1,
+                speakers: Vec::new(),
+            },
+            model_path: PathBuf::from(format!("{}.onnx", id)),
+            config_path: PathBuf::from(format!("{}.onnx.json", id)),
+        }
+    }
+
+    fn test_options() -> SynthesisOptions {
+        SynthesisOptions {
+            speaker_id: None,
+            prosody: Prosody::default(),
+        }
+    }
+
+    #[cfg(unix)]
+    fn assert_frame(samples: &[f32], n: usize) {
+        assert_eq!(samples.len(), n * 200);
+        assert!(samples.iter().all(|s| *s == (n * 257) as f32 / 32768.0));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn frames_consecutive_utterances_by_logged_completion() {
+        let piper = fake_piper("piper-logged", &format!("{}\n{}", WRITE_AUDIO, LOG_DONE));
+        let mut worker = spawn_fake(&piper);
+
+        let first = worker.synthesize("One.", None).unwrap();
+        let second = worker.synthesize("Two.", None).unwrap();
+        drop(worker);
+        fs::remove_file(&piper).unwrap();
+
+        assert_frame(&first, 1);
+        assert_frame(&second, 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn frames_consecutive_utterances_without_a_log() {
+        let piper = fake_piper("piper-quiet", WRITE_AUDIO);
+        let mut worker = spawn_fake(&piper);
+
+        let first = worker.synthesize("One.", None).unwrap();
+        let second = worker.synthesize("Two.", None).unwrap();
+        drop(worker);
+        fs::remove_file(&piper).unwrap();
+
+        assert_frame(&first, 1);
+        assert_frame(&second, 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn times_out_when_piper_hangs() {
+        let piper = fake_piper("piper-hung", "sleep 5");
+        let mut worker = spawn_fake(&piper);
+        worker.timeout = Duration::from_millis(500);
+
+        let started = Instant::now();
+        assert!(worker.synthesize("One.", None).is_err());
+        assert!(started.elapsed() < Duration::from_secs(2));
+        drop(worker);
+        fs::remove_file(&piper).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn retries_on_a_fresh_worker_after_a_crash() {
+        // The first process to get a request exits without answering
+        let crashed = std::env::temp_dir().join(format!("parrot-piper-crashed-{}", std::process::id()));
+        let body = format!(
+            "if [ ! -e {0} ]; then touch {0}; exit 1; fi\n{1}\n{2}",
+            crashed.display(),
+            WRITE_AUDIO,
+            LOG_DONE
+        );
+        let piper = fake_piper("piper-crashing", &body);
+
+        let mut backend = PiperBackend::new();
+        backend.set_piper_path(piper.clone()).unwrap();
+        backend.add_voice(test_voice("test"));
+        let options = test_options();
+
+        let first = backend.synthesize("test", "One.", &options).unwrap();
+        let second = backend.synthesize("test", "Two.", &options).unwrap();
+        drop(backend);
+        fs::remove_file(&piper).unwrap();
+        fs::remove_file(&crashed).unwrap();
+
+        // The retry went to a new process, which counts from one again
+        assert_frame(&first, 1);
+        assert_frame(&second, 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_workers_for_recent_voices_only() {
+        let piper = fake_piper("piper-voices", &format!("{}\n{}", WRITE_AUDIO, LOG_DONE));
+        let mut backend = PiperBackend::new();
+        backend.set_piper_path(piper.clone()).unwrap();
+        let ids = ["a", "b", "c", "d"];
+        backend.set_voices(ids.iter().map(|id| test_voice(id)).collect());
+
+        for id in ["a", "b", "c", "a", "d"] {
+            backend.synthesize(id, "Hello.", &test_options()).unwrap();
+        }
+        let running: Vec<String> = backend.workers.iter().map(|(id, _)| id.clone()).collect();
+        drop(backend);
+        fs::remove_file(&piper).unwrap();
+
+        // "b" was the least recently used when "d" needed a worker
+        assert_eq!(running, ["c", "a", "d"]);
+    }
+}
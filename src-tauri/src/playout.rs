@@ -0,0 +1,153 @@
+use anyhow::{anyhow, Result};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// Samples quieter than this at either end of a sentence count as silence
+const SILENCE_THRESHOLD: f32 = 1e-3;
+
+/// When synthesized speech is allowed to start playing. Without it, the delay
+/// before a reply and the pauses inside it follow how long the user spoke and
+/// how fast each sentence was transcribed and synthesized.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlayoutTiming {
+    pub enabled: bool,
+    /// Replies start this long after the segmenter finished the utterance,
+    /// however quickly they were ready
+    pub latency_budget_ms: u64,
+    /// Replies that miss the budget start on the next multiple of this past
+    /// it; 0 starts them as soon as they are ready
+    pub quantum_ms: u64,
+    /// Random extra delay of up to this much before each reply
+    pub jitter_ms: u64,
+    /// Silence between the sentences of a reply, replacing the TTS's own
+    pub sentence_gap_ms: u64,
+}
+
+impl Default for PlayoutTiming {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            latency_budget_ms: 2500,
+            quantum_ms: 500,
+            jitter_ms: 300,
+            sentence_gap_ms: 400,
+        }
+    }
+}
+
+impl PlayoutTiming {
+    pub fn validate(&self) -> Result<()> {
+        if self.latency_budget_ms > 10000 {
+            return Err(anyhow!("Latency budget must be at most 10000 ms"));
+        }
+        if self.quantum_ms > 5000 {
+            return Err(anyhow!("Start quantum must be at most 5000 ms"));
+        }
+        if self.jitter_ms > 2000 {
+            return Err(anyhow!("Jitter must be at most 2000 ms"));
+        }
+        if self.sentence_gap_ms > 2000 {
+            return Err(anyhow!("Sentence gap must be at most 2000 ms"));
+        }
+        Ok(())
+    }
+}
+
+/// Where a sentence goes in the output
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Slot {
+    /// When to queue the sentence
+    pub at: Instant,
+    /// Samples of silence to queue ahead of it
+    pub gap: usize,
+}
+
+/// Decides when each synthesized sentence starts. The first sentence of a
+/// reply waits for the latency budget; the rest follow the sentence before
+/// them after a fixed gap.
+pub struct PlayoutScheduler {
+    sample_rate: u32,
+    /// When the previous sentence's utterance was heard
+    last_heard: Option<Instant>,
+    /// When the previous sentence finishes playing
+    last_end: Option<Instant>,
+}
+
+impl PlayoutScheduler {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            last_heard: None,
+            last_end: None,
+        }
+    }
+
+    /// Schedules a sentence of the utterance heard at `heard_at`, with
+    /// `buffered` samples still waiting to be played
+    pub fn schedule(
+        &mut self,
+        timing: &PlayoutTiming,
+        heard_at: Instant,
+        buffered: usize,
+        now: Instant,
+        rng: &mut impl Rng,
+    ) -> Slot {
+        let gap = Duration::from_millis(timing.sentence_gap_ms);
+        let same_reply = self.last_heard == Some(heard_at);
+        self.last_heard = Some(heard_at);
+
+        if buffered > 0 {
+            // Still speaking: join on after the gap, whichever reply this is
+            return Slot {
+                at: now,
+                gap: self.samples_for(gap),
+            };
+        }
+        if let (true, Some(last_end)) = (same_reply, self.last_end) {
+            // The rest of a reply whose earlier sentences already finished
+            return Slot {
+                at: (last_end + gap).max(now),
+                gap: 0,
+            };
+        }
+
+        let due = heard_at + Duration::from_millis(timing.latency_budget_ms);
+        let mut at = due;
+        if now > due {
+            let late = now - due;
+            log::warn!("Reply missed the latency budget by {} ms", late.as_millis());
+            if timing.quantum_ms > 0 {
+                let quanta = (late.as_millis() as u64).div_ceil(timing.quantum_ms);
+                at = due + Duration::from_millis(quanta * timing.quantum_ms);
+            } else {
+                at = now;
+            }
+        }
+        if timing.jitter_ms > 0 {
+            at += Duration::from_millis(rng.gen_range(0..=timing.jitter_ms));
+        }
+        Slot { at, gap: 0 }
+    }
+
+    /// Records when the output will have played everything queued so far
+    pub fn queued(&mut self, now: Instant, buffered: usize) {
+        self.last_end = Some(now + Duration::from_secs_f64(buffered as f64 / self.sample_rate as f64));
+    }
+
+    fn samples_for(&self, duration: Duration) -> usize {
+        (duration.as_secs_f64() * self.sample_rate as f64) as usize
+    }
+}
+
+/// Cuts the silence the TTS leaves at either end of a sentence, so the gaps
+/// between sentences are the scheduler's alone
+pub fn trim_silence(audio: &[f32]) -> &[f32] {
+    let start = audio.iter().position(|s| s.abs() > SILENCE_THRESHOLD);
+    let end = audio.iter().rposition(|s| s.abs() > SILENCE_THRESHOLD);
+    match (start, end) {
+        (Some(start), Some(end)) => &audio[start..=end],
+        _ => &[],
+    }
+}
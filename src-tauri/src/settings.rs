@@ -1,22 +1,112 @@
+use crate::calibration::Calibration;
+use crate::command_stt::CommandSttConfig;
+use crate::command_tts::CommandVoiceConfig;
+use crate::disguise::DisguiseSettings;
+use crate::identity::IdentitySettings;
+use crate::pipeline::{InputMode, InputProcessing};
+use crate::playout::PlayoutTiming;
+use crate::stt::{SttBackendKind, SttOptions};
+use crate::tts::Prosody;
+use crate::vad::{VadConfig, VadSettings};
 use anyhow::{Context, Result};
+use rand::Rng;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
 
 const APP_NAME: &str = "parrot";
 const SETTINGS_FILE: &str = "settings.json";
 
+/// Voice ids from before voices were discovered on disk, with the model file
+/// stems that are their ids now
+const LEGACY_VOICE_IDS: [(&str, &str); 3] = [
+    ("lessac", "en_US-lessac-medium"),
+    ("ryan", "en_US-ryan-medium"),
+    ("alba", "en_GB-alba-medium"),
+];
+const VOICES_DIR: &str = "voices";
+const MODELS_DIR: &str = "models";
+const SESSION_LOG_FILE: &str = "sessions.jsonl";
+/// Name of the disguise preset generated on first run
+const PERSONAL_PRESET: &str = "Personal";
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Settings {
     pub input_device: Option<String>,
     pub output_device: Option<String>,
     pub voice_id: Option<String>,
-    #[serde(default = "default_silence_duration")]
-    pub silence_duration_ms: u64,
+    /// Speaker within a multi-speaker voice
+    #[serde(default)]
+    pub speaker_id: Option<u32>,
+    /// Utterance segmentation. Flattened so `silence_duration_ms` stays where
+    /// the frontend reads and writes it.
+    #[serde(flatten)]
+    pub vad_config: VadConfig,
+    /// Extra directories to scan for Piper voices
+    #[serde(default)]
+    pub voice_dirs: Vec<PathBuf>,
+    /// Global TTS prosody
+    #[serde(default)]
+    pub prosody: Prosody,
+    /// Per-voice prosody overrides, keyed by voice id
+    #[serde(default)]
+    pub voice_prosody: HashMap<String, Prosody>,
+    /// Voices backed by user-defined command templates
+    #[serde(default)]
+    pub command_voices: Vec<CommandVoiceConfig>,
+    /// Speech-to-text engine in use
+    #[serde(default)]
+    pub stt_backend: SttBackendKind,
+    /// External recognizer used by the command STT backend
+    #[serde(default)]
+    pub stt_command: Option<CommandSttConfig>,
+    /// Spoken language and translate mode
+    #[serde(default)]
+    pub stt_options: SttOptions,
+    /// Speak each transcript with a voice for its language
+    #[serde(default)]
+    pub match_voice_language: bool,
+    /// Whisper model in use, as a model id or a path
+    #[serde(default)]
+    pub whisper_model: Option<String>,
+    /// Speak stable parts of a transcript before the user stops talking
+    #[serde(default)]
+    pub streaming: bool,
+    /// Voice activity detector tuning
+    #[serde(default)]
+    pub vad: VadSettings,
+    /// Always-on VAD, push-to-talk or toggle
+    #[serde(default)]
+    pub input_mode: InputMode,
+    /// Gain applied to the microphone
+    #[serde(default = "default_input_gain")]
+    pub input_gain: f32,
+    /// Outcome of the last microphone calibration
+    #[serde(default)]
+    pub calibration: Option<Calibration>,
+    /// Input processing per input device, keyed by device name
+    #[serde(default)]
+    pub input_processing: HashMap<String, InputProcessing>,
+    /// Named voice disguise presets
+    #[serde(default)]
+    pub disguise_presets: HashMap<String, DisguiseSettings>,
+    /// Disguise preset applied to synthesized speech, if any
+    #[serde(default)]
+    pub disguise_preset: Option<String>,
+    /// Whether the first-run disguise preset has been generated
+    #[serde(default)]
+    pub disguise_seeded: bool,
+    /// Random voice identity per session
+    #[serde(default)]
+    pub random_identity: IdentitySettings,
+    /// Scheduling of when synthesized speech starts
+    #[serde(default)]
+    pub playout_timing: PlayoutTiming,
 }
 
-fn default_silence_duration() -> u64 {
-    700
+fn default_input_gain() -> f32 {
+    1.0
 }
 
 impl Settings {
@@ -25,10 +115,46 @@ impl Settings {
             input_device: None,
             output_device: None,
             voice_id: None,
-            silence_duration_ms: default_silence_duration(),
+            speaker_id: None,
+            vad_config: VadConfig::default(),
+            voice_dirs: Vec::new(),
+            prosody: Prosody::default(),
+            voice_prosody: HashMap::new(),
+            command_voices: Vec::new(),
+            stt_backend: SttBackendKind::default(),
+            stt_command: None,
+            stt_options: SttOptions::default(),
+            match_voice_language: false,
+            whisper_model: None,
+            streaming: false,
+            vad: VadSettings::default(),
+            input_mode: InputMode::default(),
+            input_gain: default_input_gain(),
+            calibration: None,
+            input_processing: HashMap::new(),
+            disguise_presets: HashMap::new(),
+            disguise_preset: None,
+            disguise_seeded: false,
+            random_identity: IdentitySettings::default(),
+            playout_timing: PlayoutTiming::default(),
         }
     }
 
+    /// Per-user directory where voices can be dropped without touching the install
+    pub fn user_voices_dir() -> Option<PathBuf> {
+        dirs::config_dir().map(|p| p.join(APP_NAME).join(VOICES_DIR))
+    }
+
+    /// Per-user directory for additional Whisper models
+    pub fn user_models_dir() -> Option<PathBuf> {
+        dirs::config_dir().map(|p| p.join(APP_NAME).join(MODELS_DIR))
+    }
+
+    /// Where the identities picked for each session are recorded
+    pub fn session_log_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|p| p.join(APP_NAME).join(SESSION_LOG_FILE))
+    }
+
     fn settings_path() -> Option<PathBuf> {
         dirs::config_dir().map(|p| p.join(APP_NAME).join(SETTINGS_FILE))
     }
@@ -45,13 +171,46 @@ impl Settings {
         let contents = fs::read_to_string(&path)
             .with_context(|| format!("Failed to read settings from {:?}", path))?;
 
-        let settings: Settings = serde_json::from_str(&contents)
+        let mut settings: Settings = serde_json::from_str(&contents)
             .with_context(|| format!("Failed to parse settings from {:?}", path))?;
 
+        if let Some((old, stem)) = LEGACY_VOICE_IDS
+            .iter()
+            .find(|(old, _)| settings.voice_id.as_deref() == Some(*old))
+        {
+            log::info!("Migrating saved voice {} to {}", old, stem);
+            settings.voice_id = Some(stem.to_string());
+        }
+
         log::info!("Loaded settings from {:?}", path);
         Ok(settings)
     }
 
+    /// Adds a randomly generated disguise preset the first time the app runs,
+    /// so users of the same voice don't all sound alike. It is selected unless
+    /// another preset already is. Returns whether the settings changed.
+    pub fn seed_disguise_preset(&mut self, rng: &mut impl Rng) -> bool {
+        if self.disguise_seeded {
+            return false;
+        }
+        self.disguise_presets
+            .entry(PERSONAL_PRESET.to_string())
+            .or_insert_with(|| DisguiseSettings::random(rng));
+        if self.disguise_preset.is_none() {
+            self.disguise_preset = Some(PERSONAL_PRESET.to_string());
+        }
+        self.disguise_seeded = true;
+        true
+    }
+
+    /// Loads the settings file, applies `f` and writes it back. Used by commands
+    /// that persist their own values.
+    pub fn update<F: FnOnce(&mut Settings)>(f: F) -> Result<()> {
+        let mut settings = Self::load()?;
+        f(&mut settings);
+        settings.save()
+    }
+
     pub fn save(&self) -> Result<()> {
         let path = Self::settings_path()
             .context("Could not determine config directory")?;
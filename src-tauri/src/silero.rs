@@ -0,0 +1,251 @@
+use crate::audio::scrub;
+use crate::vad::VadSettings;
+use anyhow::{anyhow, Context, Result};
+use ort::session::Session;
+use ort::value::Tensor;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+
+/// Silero runs at 16 kHz
+const SAMPLE_RATE: u32 = 16000;
+/// Samples per inference: 32 ms at 16 kHz
+const WINDOW: usize = 512;
+const WINDOW_MS: u32 = 32;
+/// The model also sees the tail of the previous window
+const CONTEXT: usize = 64;
+/// Recurrent state carried between windows, shaped [2, 1, 128]
+const STATE_LEN: usize = 256;
+/// Speech ends once the probability drops this far below the start threshold
+const CLOSE_MARGIN: f32 = 0.15;
+/// Input blocks that may wait for inference before new ones are dropped
+const QUEUE_BLOCKS: usize = 64;
+
+#[cfg(windows)]
+const RUNTIME_LIBRARY: &str = "onnxruntime.dll";
+#[cfg(target_os = "macos")]
+const RUNTIME_LIBRARY: &str = "libonnxruntime.dylib";
+#[cfg(not(any(windows, target_os = "macos")))]
+const RUNTIME_LIBRARY: &str = "libonnxruntime.so";
+
+/// Points ONNX Runtime at the first copy of its shared library found in
+/// `dirs`. Piper ships one, so the bundled copy is normally picked up; if none
+/// is found the system library search path is used.
+pub fn init_runtime(dirs: &[PathBuf]) -> Result<()> {
+    let builder = match dirs.iter().map(|d| d.join(RUNTIME_LIBRARY)).find(|p| p.exists()) {
+        Some(path) => {
+            log::info!("Using ONNX Runtime from {:?}", path);
+            ort::init_from(path.display().to_string())
+        }
+        None => ort::init(),
+    };
+
+    builder
+        .with_name("parrot")
+        .commit()
+        .map_err(|e| anyhow!("Failed to initialize ONNX Runtime: {}", e))?;
+    Ok(())
+}
+
+/// Runs Silero on its own thread, so a slow inference can't hold up the audio
+/// callback that feeds it. Blocks are queued to the thread and the speech state
+/// it last reached is read back without waiting, which puts the decision a
+/// window or so behind the input; the pre-roll covers that.
+pub struct SileroWorker {
+    sender: SyncSender<(Vec<f32>, VadSettings)>,
+    active: Arc<AtomicBool>,
+    failed: Arc<AtomicBool>,
+}
+
+impl SileroWorker {
+    pub fn load(model_path: &Path, input_sample_rate: u32) -> Result<Self> {
+        let mut vad = SileroVad::load(model_path, input_sample_rate)?;
+        let (sender, receiver) = sync_channel::<(Vec<f32>, VadSettings)>(QUEUE_BLOCKS);
+        let active = Arc::new(AtomicBool::new(false));
+        let failed = Arc::new(AtomicBool::new(false));
+
+        let thread_active = Arc::clone(&active);
+        let thread_failed = Arc::clone(&failed);
+        thread::spawn(move || {
+            // Ends once the worker is dropped and the queue runs dry
+            for (mut samples, settings) in receiver {
+                let result = vad.process(&samples, &settings);
+                scrub(&mut samples);
+                match result {
+                    Ok(active) => thread_active.store(active, Ordering::SeqCst),
+                    Err(e) => {
+                        log::error!("Silero VAD inference failed: {}", e);
+                        thread_failed.store(true, Ordering::SeqCst);
+                        break;
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            sender,
+            active,
+            failed,
+        })
+    }
+
+    /// Queues a block of mono samples and returns the latest speech state
+    /// without waiting for the block to be scored
+    pub fn process(&self, samples: &[f32], settings: &VadSettings) -> Result<bool> {
+        if self.failed.load(Ordering::SeqCst) {
+            return Err(anyhow!("Silero VAD stopped"));
+        }
+        match self.sender.try_send((samples.to_vec(), *settings)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => log::debug!("Silero VAD is behind, dropping a block"),
+            Err(TrySendError::Disconnected(_)) => return Err(anyhow!("Silero VAD stopped")),
+        }
+        Ok(self.active.load(Ordering::SeqCst))
+    }
+}
+
+/// Silero VAD v5 running on the CPU through ONNX Runtime.
+///
+/// Input arrives at the device rate in blocks of any size. It is resampled to
+/// 16 kHz and scored in 512-sample windows, and the per-window speech
+/// probability is turned into a speech state with the same attack and
+/// hangover times as the energy detector.
+pub struct SileroVad {
+    session: Session,
+    state: Vec<f32>,
+    context: Vec<f32>,
+    /// 16 kHz samples waiting for a full window
+    pending: Vec<f32>,
+    resampler: LinearResampler,
+    active: bool,
+    /// Time the probability has been on the other side of the threshold
+    run_ms: u32,
+}
+
+impl SileroVad {
+    pub fn load(model_path: &Path, input_sample_rate: u32) -> Result<Self> {
+        if !model_path.exists() {
+            return Err(anyhow!("Silero VAD model not found: {:?}", model_path));
+        }
+
+        // One thread is plenty for a model this small and keeps it off the Whisper cores
+        let session = Session::builder()
+            .and_then(|b| b.with_intra_threads(1))
+            .and_then(|b| b.with_inter_threads(1))
+            .and_then(|b| b.commit_from_file(model_path))
+            .with_context(|| format!("Failed to load Silero VAD model {:?}", model_path))?;
+
+        log::info!("Loaded Silero VAD model from {:?}", model_path);
+
+        Ok(Self {
+            session,
+            state: vec![0.0; STATE_LEN],
+            context: vec![0.0; CONTEXT],
+            pending: Vec::with_capacity(WINDOW * 2),
+            resampler: LinearResampler::new(input_sample_rate, SAMPLE_RATE),
+            active: false,
+            run_ms: 0,
+        })
+    }
+
+    /// Feeds a block of mono samples and returns whether speech is active
+    pub fn process(&mut self, samples: &[f32], settings: &VadSettings) -> Result<bool> {
+        self.resampler.process(samples, &mut self.pending);
+
+        let open = settings.speech_probability;
+        let close = (open - CLOSE_MARGIN).max(0.05);
+
+        while self.pending.len() >= WINDOW {
+            let mut window: Vec<f32> = self.pending.drain(..WINDOW).collect();
+            let probability = self.infer(&window);
+            scrub(&mut window);
+            let probability = probability?;
+
+            let crossed = if self.active { probability < close } else { probability > open };
+            if crossed {
+                self.run_ms += WINDOW_MS;
+                let needed = if self.active { settings.hangover_ms() } else { settings.attack_ms() };
+                if self.run_ms >= needed {
+                    self.active = !self.active;
+                    self.run_ms = 0;
+                }
+            } else {
+                self.run_ms = 0;
+            }
+        }
+
+        Ok(self.active)
+    }
+
+    /// Scores one window and returns its speech probability
+    fn infer(&mut self, window: &[f32]) -> Result<f32> {
+        let mut input = Vec::with_capacity(CONTEXT + WINDOW);
+        input.extend_from_slice(&self.context);
+        input.extend_from_slice(window);
+
+        let outputs = self.session.run(ort::inputs![
+            "input" => Tensor::from_array(([1usize, CONTEXT + WINDOW], input))?,
+            "state" => Tensor::from_array(([2usize, 1, 128], self.state.clone()))?,
+            "sr" => Tensor::from_array(((), vec![SAMPLE_RATE as i64]))?,
+        ])?;
+
+        let (_, probability) = outputs["output"].try_extract_tensor::<f32>()?;
+        let (_, state) = outputs["stateN"].try_extract_tensor::<f32>()?;
+
+        let probability = probability
+            .first()
+            .copied()
+            .ok_or_else(|| anyhow!("Silero VAD returned no output"))?;
+        self.state.copy_from_slice(state);
+        self.context.copy_from_slice(&window[WINDOW - CONTEXT..]);
+
+        Ok(probability)
+    }
+}
+
+impl Drop for SileroVad {
+    /// Wipes the microphone audio still waiting for, or kept from, a window
+    fn drop(&mut self) {
+        scrub(&mut self.pending);
+        scrub(&mut self.context);
+    }
+}
+
+/// Streaming linear-interpolation resampler. Rough, but fine for detecting
+/// speech, and unlike `resample_audio` it carries its position across blocks.
+struct LinearResampler {
+    /// Input samples per output sample
+    step: f64,
+    /// Position of the next output sample, where 0 is `previous` and k is the
+    /// k-th sample of the current block
+    position: f64,
+    previous: f32,
+}
+
+impl LinearResampler {
+    fn new(from_rate: u32, to_rate: u32) -> Self {
+        Self {
+            step: from_rate as f64 / to_rate as f64,
+            position: 1.0,
+            previous: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        while self.position < input.len() as f64 {
+            let index = self.position as usize;
+            let frac = (self.position - index as f64) as f32;
+            let a = if index == 0 { self.previous } else { input[index - 1] };
+            let b = input[index];
+            output.push(a + (b - a) * frac);
+            self.position += self.step;
+        }
+
+        self.position -= input.len() as f64;
+        if let Some(&last) = input.last() {
+            self.previous = last;
+        }
+    }
+}
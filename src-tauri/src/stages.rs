@@ -0,0 +1,470 @@
+use crate::audio::{scrub, OutputBuffer};
+use crate::identity;
+use crate::pipeline::{emit_status, PipelineState};
+use crate::playout::PlayoutScheduler;
+use crate::streaming::StreamingTranscriber;
+use crate::tts::{split_sentences, SynthesizedAudio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender, TryRecvError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Runtime};
+
+/// Finished utterances waiting for STT. The segmenter only blocks once this many pile up.
+const STT_QUEUE: usize = 4;
+const TEXT_QUEUE: usize = 8;
+const SPEECH_QUEUE: usize = 16;
+const PLAYOUT_QUEUE: usize = 4;
+/// How often an idle TTS stage checks for a scheduled identity change
+const IDENTITY_CHECK_MS: u64 = 1000;
+
+/// Audio sent from the VAD segmenter to the STT stage. `heard_at` is when the
+/// segmenter sent it, so reply timing doesn't depend on how long it queued.
+pub enum SttJob {
+    /// Everything captured so far of an utterance that is still in progress
+    Partial { audio: Vec<f32>, heard_at: Instant },
+    /// A complete utterance
+    Final { audio: Vec<f32>, heard_at: Instant },
+}
+
+impl Drop for SttJob {
+    /// Microphone audio is wiped as soon as the STT stage is done with it
+    fn drop(&mut self) {
+        match self {
+            SttJob::Partial { audio, .. } | SttJob::Final { audio, .. } => scrub(audio),
+        }
+    }
+}
+
+/// Recognized text on its way to TTS
+struct TextJob {
+    text: String,
+    language: Option<String>,
+    /// When the segmenter finished the audio it came from
+    heard_at: Instant,
+}
+
+/// One sentence to synthesize
+struct SpeechJob {
+    text: String,
+    language: Option<String>,
+    heard_at: Instant,
+}
+
+/// One synthesized sentence at the output device's rate
+struct PlayoutJob {
+    audio: SynthesizedAudio,
+    heard_at: Instant,
+}
+
+/// Tracks which stages are busy so "pipeline-status" reflects the whole pipeline
+#[derive(Default)]
+struct Activity {
+    transcribing: AtomicBool,
+    speaking: AtomicBool,
+}
+
+impl Activity {
+    fn set_transcribing<R: Runtime>(&self, app: &AppHandle<R>, busy: bool) {
+        self.transcribing.store(busy, Ordering::SeqCst);
+        self.emit(app);
+    }
+
+    fn set_speaking<R: Runtime>(&self, app: &AppHandle<R>, busy: bool) {
+        self.speaking.store(busy, Ordering::SeqCst);
+        self.emit(app);
+    }
+
+    fn emit<R: Runtime>(&self, app: &AppHandle<R>) {
+        let status = if self.speaking.load(Ordering::SeqCst) {
+            "speaking"
+        } else if self.transcribing.load(Ordering::SeqCst) {
+            "processing"
+        } else {
+            "listening"
+        };
+        emit_status(app, status);
+    }
+}
+
+/// Handles to the running stages
+pub struct Stages {
+    pub stt_sender: SyncSender<SttJob>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl Stages {
+    /// Closes the STT queue and waits for every stage to wind down. Each
+    /// stage exits once its input is closed, so this drains front to back.
+    pub fn shutdown(self) {
+        drop(self.stt_sender);
+        for handle in self.handles {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Starts the stages behind the VAD segmenter: STT → text processing → TTS →
+/// playout. Each runs on its own thread and feeds the next over a bounded
+/// channel. Every stage has a single worker and channels are FIFO, so
+/// utterances come out in the order they were spoken, while utterance N+1 is
+/// transcribed as N is being spoken.
+pub fn spawn_stages<R: Runtime>(
+    state: Arc<PipelineState>,
+    app: AppHandle<R>,
+    stop_signal: Arc<AtomicBool>,
+    input_sample_rate: u32,
+    output_sample_rate: u32,
+    output_buffer: Arc<OutputBuffer>,
+) -> Stages {
+    let activity = Arc::new(Activity::default());
+
+    let (stt_sender, stt_receiver) = sync_channel::<SttJob>(STT_QUEUE);
+    let (text_sender, text_receiver) = sync_channel::<TextJob>(TEXT_QUEUE);
+    let (speech_sender, speech_receiver) = sync_channel::<SpeechJob>(SPEECH_QUEUE);
+    let (playout_sender, playout_receiver) = sync_channel::<PlayoutJob>(PLAYOUT_QUEUE);
+
+    let handles = vec![
+        {
+            let state = Arc::clone(&state);
+            let app = app.clone();
+            let stop_signal = Arc::clone(&stop_signal);
+            let activity = Arc::clone(&activity);
+            thread::spawn(move || {
+                stt_stage(state, app, stop_signal, activity, input_sample_rate, stt_receiver, text_sender)
+            })
+        },
+        {
+            let stop_signal = Arc::clone(&stop_signal);
+            thread::spawn(move || text_stage(stop_signal, text_receiver, speech_sender))
+        },
+        {
+            let state = Arc::clone(&state);
+            let stop_signal = Arc::clone(&stop_signal);
+            thread::spawn(move || {
+                tts_stage(state, app, stop_signal, activity, output_sample_rate, speech_receiver, playout_sender)
+            })
+        },
+        thread::spawn(move || {
+            playout_stage(state, stop_signal, output_sample_rate, playout_receiver, output_buffer)
+        }),
+    ];
+
+    Stages {
+        stt_sender,
+        handles,
+    }
+}
+
+/// Transcribes utterances. In streaming mode it also transcribes partial
+/// utterances and passes on text as soon as it is stable.
+fn stt_stage<R: Runtime>(
+    state: Arc<PipelineState>,
+    app: AppHandle<R>,
+    stop_signal: Arc<AtomicBool>,
+    activity: Arc<Activity>,
+    input_sample_rate: u32,
+    receiver: Receiver<SttJob>,
+    sender: SyncSender<TextJob>,
+) {
+    let mut streamer = StreamingTranscriber::new();
+    // Samples at the start of the utterance that streaming has fully committed
+    let mut window_start = 0;
+
+    for job in receiver {
+        if stop_signal.load(Ordering::SeqCst) {
+            break;
+        }
+
+        match &job {
+            SttJob::Partial { audio, heard_at } => {
+                let window = &audio[window_start.min(audio.len())..];
+
+                let transcript = {
+                    let stt = state.stt.lock().unwrap();
+                    let backend = stt.backend();
+                    if backend.is_ready() {
+                        backend
+                            .transcribe_partial(window, input_sample_rate, stt.options())
+                            .ok()
+                    } else {
+                        None
+                    }
+                };
+
+                if let Some(transcript) = transcript {
+                    let update = streamer.update(&transcript);
+                    let _ = app.emit("transcript-partial", &update.partial);
+
+                    // Later passes only need the audio that hasn't been committed yet
+                    if let Some(seconds) = update.consumed {
+                        window_start += ((seconds * input_sample_rate as f32) as usize).min(window.len());
+                    }
+
+                    if let Some(text) = update.commit {
+                        log::info!("Committed: {}", text);
+                        let job = TextJob {
+                            text,
+                            language: transcript.language,
+                            heard_at: *heard_at,
+                        };
+                        if sender.send(job).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+            SttJob::Final { audio, heard_at } => {
+                log::info!("Processing {} samples", audio.len());
+                activity.set_transcribing(&app, true);
+
+                let utterance = std::mem::take(&mut streamer);
+                let window = &audio[std::mem::take(&mut window_start).min(audio.len())..];
+
+                let transcript = {
+                    let stt = state.stt.lock().unwrap();
+                    let backend = stt.backend();
+                    if backend.is_ready() {
+                        backend.transcribe(window, input_sample_rate, stt.options()).ok()
+                    } else {
+                        log::warn!("Speech-to-text backend not ready");
+                        None
+                    }
+                };
+
+                activity.set_transcribing(&app, false);
+
+                if let Some(transcript) = transcript {
+                    if let Some(language) = &transcript.language {
+                        let _ = app.emit("detected-language", language);
+                    }
+                    // Leave out whatever streaming already spoke
+                    let text = if utterance.has_committed() {
+                        utterance.finish(&transcript)
+                    } else {
+                        transcript.text.trim().to_string()
+                    };
+                    let job = TextJob {
+                        text,
+                        language: transcript.language,
+                        heard_at: *heard_at,
+                    };
+                    if sender.send(job).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Drops transcripts that aren't speech and splits the rest into sentences
+fn text_stage(
+    stop_signal: Arc<AtomicBool>,
+    receiver: Receiver<TextJob>,
+    sender: SyncSender<SpeechJob>,
+) {
+    for job in receiver {
+        if stop_signal.load(Ordering::SeqCst) {
+            break;
+        }
+
+        let text = job.text.trim();
+        // Filter out blank audio markers and very short/noisy transcriptions
+        if text.is_empty() || text.contains("[BLANK_AUDIO]") || text.len() <= 1 {
+            continue;
+        }
+        log::info!("Transcribed: {}", text);
+
+        for sentence in split_sentences(text) {
+            let job = SpeechJob {
+                text: sentence,
+                language: job.language.clone(),
+                heard_at: job.heard_at,
+            };
+            if sender.send(job).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Synthesizes sentences and converts them to the output device's rate. Each
+/// sentence is handed to playout as soon as it is rendered, so a long
+/// utterance starts playing while the rest is still being synthesized.
+fn tts_stage<R: Runtime>(
+    state: Arc<PipelineState>,
+    app: AppHandle<R>,
+    stop_signal: Arc<AtomicBool>,
+    activity: Arc<Activity>,
+    output_sample_rate: u32,
+    receiver: Receiver<SpeechJob>,
+    sender: SyncSender<PlayoutJob>,
+) {
+    loop {
+        let job = match receiver.try_recv() {
+            Ok(job) => job,
+            Err(TryRecvError::Empty) => {
+                // Nothing queued: report idle, then wait for the next sentence
+                if activity.speaking.load(Ordering::SeqCst) {
+                    activity.set_speaking(&app, false);
+                }
+                // Between replies is the only time the voice may change, so
+                // the wait wakes up now and then for a scheduled identity change
+                loop {
+                    identity::rotate_if_due(&state, &app);
+                    match receiver.recv_timeout(Duration::from_millis(IDENTITY_CHECK_MS)) {
+                        Ok(job) => break job,
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                }
+            }
+            Err(TryRecvError::Disconnected) => break,
+        };
+
+        if stop_signal.load(Ordering::SeqCst) {
+            break;
+        }
+        if !activity.speaking.load(Ordering::SeqCst) {
+            activity.set_speaking(&app, true);
+        }
+
+        let synthesized = {
+            let mut tts = state.tts.lock().unwrap();
+            if tts.is_ready() {
+                tts.synthesize(&job.text, job.language.as_deref()).ok()
+            } else {
+                log::warn!("TTS not ready");
+                None
+            }
+        };
+
+        let audio = match synthesized {
+            Some(audio) => audio,
+            None => continue,
+        };
+
+        log::info!("Synthesized {} samples at {} Hz", audio.len(), audio.sample_rate());
+
+        // Disguise at the voice's own rate, before resampling
+        let disguise = state.get_disguise();
+        let audio = if disguise.is_neutral() {
+            audio
+        } else {
+            audio.disguised(&disguise)
+        };
+
+        // Resample TTS output to match output device sample rate
+        let resampled = if audio.sample_rate() != output_sample_rate {
+            log::info!("Resampling from {} Hz to {} Hz", audio.sample_rate(), output_sample_rate);
+            match audio.resampled(output_sample_rate) {
+                Ok(resampled) => resampled,
+                Err(e) => {
+                    log::error!("Resampling failed: {}", e);
+                    audio
+                }
+            }
+        } else {
+            audio
+        };
+
+        let job = PlayoutJob {
+            audio: resampled,
+            heard_at: job.heard_at,
+        };
+        if sender.send(job).is_err() {
+            break;
+        }
+    }
+}
+
+/// Queues rendered audio for the output stream. With playout timing on,
+/// each sentence waits for its slot from the scheduler.
+fn playout_stage(
+    state: Arc<PipelineState>,
+    stop_signal: Arc<AtomicBool>,
+    output_sample_rate: u32,
+    receiver: Receiver<PlayoutJob>,
+    output_buffer: Arc<OutputBuffer>,
+) {
+    let mut scheduler = PlayoutScheduler::new(output_sample_rate);
+    let mut rng = rand::thread_rng();
+
+    for job in receiver {
+        let timing = state.get_playout_timing();
+        if !timing.enabled {
+            log::info!("Output {} samples to playback buffer", job.audio.len());
+            output_buffer.push(job.audio);
+            continue;
+        }
+
+        let audio = job.audio.trimmed();
+        if audio.is_empty() {
+            continue;
+        }
+        let slot = scheduler.schedule(&timing, job.heard_at, output_buffer.len(), Instant::now(), &mut rng);
+
+        // Wait for the slot, but not past a stop
+        while Instant::now() < slot.at {
+            if stop_signal.load(Ordering::SeqCst) {
+                return;
+            }
+            thread::sleep((slot.at - Instant::now()).min(Duration::from_millis(50)));
+        }
+
+        log::info!("Output {} samples to playback buffer after {} samples of gap", audio.len(), slot.gap);
+        output_buffer.push_silence(slot.gap);
+        output_buffer.push(audio);
+        scheduler.queued(Instant::now(), output_buffer.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::playout::PlayoutTiming;
+
+    const RATE: u32 = 16000;
+
+    /// A sentence as the TTS renders it: a tone with trailing silence
+    fn sentence(tone_len: usize) -> SynthesizedAudio {
+        let mut samples: Vec<f32> = (0..tone_len)
+            .map(|i| 0.2 * (2.0 * std::f32::consts::PI * 200.0 * i as f32 / RATE as f32).sin() + 0.01)
+            .collect();
+        samples.resize(tone_len + RATE as usize / 2, 0.0);
+        SynthesizedAudio::for_test(samples, RATE)
+    }
+
+    #[test]
+    fn playout_replaces_sentence_silence_with_fixed_gaps() {
+        let state = Arc::new(PipelineState::new().unwrap());
+        let timing = PlayoutTiming {
+            enabled: true,
+            latency_budget_ms: 0,
+            quantum_ms: 0,
+            jitter_ms: 0,
+            sentence_gap_ms: 100,
+        };
+        state.set_playout_timing(timing).unwrap();
+
+        let (sender, receiver) = sync_channel(PLAYOUT_QUEUE);
+        let heard_at = Instant::now();
+        for _ in 0..3 {
+            let job = PlayoutJob {
+                audio: sentence(1000),
+                heard_at,
+            };
+            sender.send(job).unwrap();
+        }
+        drop(sender);
+
+        // Nothing is playing the buffer, so it ends up holding everything queued
+        let output = Arc::new(OutputBuffer::new());
+        playout_stage(state, Arc::new(AtomicBool::new(false)), RATE, receiver, Arc::clone(&output));
+
+        let gap = RATE as usize / 10;
+        assert_eq!(output.len(), 3 * 1000 + 2 * gap);
+    }
+}
@@ -0,0 +1,257 @@
+use crate::stt::Transcript;
+use serde::Serialize;
+
+/// Commit stable text once this many new words have settled, even without punctuation
+const MIN_COMMIT_WORDS: usize = 6;
+
+/// Payload of the `transcript-partial` event
+#[derive(Clone, Debug, Serialize)]
+pub struct PartialTranscript {
+    /// Text already handed to TTS during this utterance
+    pub committed: String,
+    /// The rest of the current hypothesis, which may still change
+    pub pending: String,
+}
+
+/// What the pipeline should do after a streaming pass
+pub struct StreamUpdate {
+    /// Newly stable text to synthesize now
+    pub commit: Option<String>,
+    /// Seconds of audio at the start of the window that are fully committed
+    /// and can be dropped from the next pass
+    pub consumed: Option<f32>,
+    pub partial: PartialTranscript,
+}
+
+/// Turns repeated transcriptions of a growing utterance into stable text.
+///
+/// Words are only committed once two consecutive passes agree on them, so a
+/// word Whisper is still unsure about is never spoken. The window is slid
+/// forward at segment boundaries that lie entirely within committed text.
+#[derive(Default)]
+pub struct StreamingTranscriber {
+    /// Words of the current window from the previous pass
+    previous: Vec<String>,
+    /// How many leading words of the current window have been committed
+    committed_words: usize,
+    /// Everything committed during this utterance
+    committed_text: Vec<String>,
+}
+
+impl StreamingTranscriber {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether any text of the current utterance has been committed
+    pub fn has_committed(&self) -> bool {
+        !self.committed_text.is_empty()
+    }
+
+    /// Processes a transcription of the audio since the start of the window
+    pub fn update(&mut self, transcript: &Transcript) -> StreamUpdate {
+        let words = transcript_words(transcript);
+
+        let agreed = self
+            .previous
+            .iter()
+            .zip(&words)
+            .take_while(|(a, b)| normalize(a) == normalize(b))
+            .count();
+
+        let commit = self.commit_point(&words, agreed).map(|end| {
+            let text = words[self.committed_words..end].join(" ");
+            self.committed_text.extend_from_slice(&words[self.committed_words..end]);
+            self.committed_words = end;
+            text
+        });
+
+        let partial = PartialTranscript {
+            committed: self.committed_text.join(" "),
+            pending: words[self.committed_words.min(words.len())..].join(" "),
+        };
+
+        // Slide past leading segments whose words have all been committed.
+        // The last segment is still growing, so it is never dropped.
+        let mut consumed = None;
+        let mut dropped_words = 0;
+        let finished = transcript.segments.len().saturating_sub(1);
+        for segment in &transcript.segments[..finished] {
+            let count = split_words(&segment.text).len();
+            if dropped_words + count > self.committed_words {
+                break;
+            }
+            dropped_words += count;
+            consumed = Some(segment.end);
+        }
+        if consumed.is_some() {
+            self.committed_words -= dropped_words;
+        }
+        self.previous = words.into_iter().skip(dropped_words).collect();
+
+        StreamUpdate {
+            commit,
+            consumed,
+            partial,
+        }
+    }
+
+    /// Returns the final transcription of the window minus what has already
+    /// been committed
+    pub fn finish(&self, transcript: &Transcript) -> String {
+        transcript_words(transcript)
+            .into_iter()
+            .skip(self.committed_words)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Picks how far to commit: through the last agreed word that ends a
+    /// phrase, or all agreed words once enough of them have piled up
+    fn commit_point(&self, words: &[String], agreed: usize) -> Option<usize> {
+        if agreed <= self.committed_words {
+            return None;
+        }
+
+        let phrase_end = (self.committed_words..agreed)
+            .rev()
+            .find(|&i| words[i].ends_with(['.', ',', '?', '!', ';', ':']))
+            .map(|i| i + 1);
+
+        phrase_end.or_else(|| (agreed - self.committed_words >= MIN_COMMIT_WORDS).then_some(agreed))
+    }
+}
+
+/// Words of a transcript, split segment by segment so a segment that is only
+/// an annotation like `(music)` counts as no words
+fn transcript_words(transcript: &Transcript) -> Vec<String> {
+    if transcript.segments.is_empty() {
+        return split_words(&transcript.text);
+    }
+    transcript
+        .segments
+        .iter()
+        .flat_map(|segment| split_words(&segment.text))
+        .collect()
+}
+
+/// Splits text into words. Text that is nothing but an annotation has none,
+/// and tags like `[BLANK_AUDIO]` are dropped wherever they are, but words the
+/// user said in parentheses are kept.
+fn split_words(text: &str) -> Vec<String> {
+    if is_annotation(text.trim()) {
+        return Vec::new();
+    }
+    text.split_whitespace()
+        .filter(|w| !(w.starts_with('[') && w.ends_with(']')))
+        .map(|w| w.to_string())
+        .collect()
+}
+
+/// Whether text is a single bracketed annotation, e.g. `[BLANK_AUDIO]` or
+/// `(upbeat music)`
+fn is_annotation(text: &str) -> bool {
+    [('[', ']'), ('(', ')')].into_iter().any(|(open, close)| {
+        text.strip_prefix(open)
+            .and_then(|t| t.strip_suffix(close))
+            .is_some_and(|inner| !inner.contains([open, close]))
+    })
+}
+
+/// Compares words ignoring case and punctuation, which Whisper often revises
+fn normalize(word: &str) -> String {
+    word.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stt::TranscriptSegment;
+
+    fn transcript(segments: &[(&str, f32)]) -> Transcript {
+        Transcript {
+            text: segments
+                .iter()
+                .map(|(text, _)| *text)
+                .collect::<Vec<_>>()
+                .join(" "),
+            language: None,
+            segments: segments
+                .iter()
+                .map(|(text, end)| TranscriptSegment {
+                    text: text.to_string(),
+                    end: *end,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn drops_annotations_but_keeps_words_in_parentheses() {
+        assert_eq!(split_words("I (really) mean it"), ["I", "(really)", "mean", "it"]);
+        assert_eq!(split_words("hello [BLANK_AUDIO] there"), ["hello", "there"]);
+        assert!(split_words("[BLANK_AUDIO]").is_empty());
+        assert!(split_words(" (upbeat music) ").is_empty());
+        assert_eq!(
+            transcript_words(&transcript(&[("(music)", 1.0), ("Hello.", 1.5)])),
+            ["Hello."]
+        );
+    }
+
+    #[test]
+    fn commits_only_words_two_passes_agree_on() {
+        let mut streaming = StreamingTranscriber::new();
+
+        let first = streaming.update(&transcript(&[("Hello there. How", 1.0)]));
+        assert_eq!(first.commit, None);
+
+        // "How" changed, so only the phrase before it is stable
+        let second = streaming.update(&transcript(&[("hello there. Who is", 1.5)]));
+        assert_eq!(second.commit.as_deref(), Some("hello there."));
+        assert_eq!(second.partial.committed, "hello there.");
+        assert_eq!(second.partial.pending, "Who is");
+        assert!(streaming.has_committed());
+    }
+
+    #[test]
+    fn commits_long_unpunctuated_runs() {
+        let mut streaming = StreamingTranscriber::new();
+        let text = "one two three four five six seven";
+
+        assert_eq!(streaming.update(&transcript(&[(text, 2.0)])).commit, None);
+        assert_eq!(streaming.update(&transcript(&[(text, 2.0)])).commit.as_deref(), Some(text));
+    }
+
+    #[test]
+    fn slides_past_committed_segments() {
+        let mut streaming = StreamingTranscriber::new();
+
+        streaming.update(&transcript(&[("Hello there.", 1.0), ("How are", 2.0)]));
+        let update = streaming.update(&transcript(&[("Hello there.", 1.0), ("How are you", 2.5)]));
+        assert_eq!(update.commit.as_deref(), Some("Hello there."));
+        assert_eq!(update.consumed, Some(1.0));
+
+        // The next window starts after the dropped segment
+        let update = streaming.update(&transcript(&[("How are you doing", 1.5)]));
+        assert_eq!(update.commit, None);
+        assert_eq!(update.consumed, None);
+        assert_eq!(update.partial.committed, "Hello there.");
+        assert_eq!(update.partial.pending, "How are you doing");
+
+        let rest = streaming.finish(&transcript(&[("How are you doing today?", 2.0)]));
+        assert_eq!(rest, "How are you doing today?");
+    }
+
+    #[test]
+    fn finish_skips_committed_words() {
+        let mut streaming = StreamingTranscriber::new();
+        let words = transcript(&[("Yes, I think so", 1.0)]);
+
+        streaming.update(&words);
+        assert_eq!(streaming.update(&words).commit.as_deref(), Some("Yes,"));
+        assert_eq!(streaming.finish(&transcript(&[("Yes, I think so.", 1.0)])), "I think so.");
+    }
+}
@@ -1,21 +1,183 @@
+use crate::audio::{resample_audio, scrub};
+use crate::command_stt::{CommandSttBackend, CommandSttConfig};
 use anyhow::{anyhow, Result};
-use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
 use whisper_rs::{FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters};
 
+/// Which speech-to-text engine the pipeline uses
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SttBackendKind {
+    #[default]
+    Whisper,
+    Command,
+}
+
+/// Language handling for transcription
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SttOptions {
+    /// Spoken language code (e.g. "en", "de"), or "auto" to detect it
+    pub language: String,
+    /// Translate the speech to English instead of transcribing it
+    pub translate: bool,
+}
+
+impl Default for SttOptions {
+    fn default() -> Self {
+        Self {
+            language: "en".to_string(),
+            translate: false,
+        }
+    }
+}
+
+impl SttOptions {
+    pub fn is_auto(&self) -> bool {
+        self.language == "auto"
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if !self.is_auto() && whisper_rs::get_lang_id(&self.language).is_none() {
+            return Err(anyhow!("Unknown language: {}", self.language));
+        }
+        Ok(())
+    }
+}
+
+/// Result of transcribing one utterance
+#[derive(Clone, Debug)]
+pub struct Transcript {
+    pub text: String,
+    /// Language the text is in: the detected or configured spoken language,
+    /// or "en" when translating
+    pub language: Option<String>,
+    /// Timed pieces of `text`, in order. Empty if the backend doesn't report timing.
+    pub segments: Vec<TranscriptSegment>,
+}
+
+/// A piece of a transcript and where it ends in the audio
+#[derive(Clone, Debug)]
+pub struct TranscriptSegment {
+    pub text: String,
+    /// Offset of the end of the segment from the start of the audio, in seconds
+    pub end: f32,
+}
+
+/// A local speech recognizer turning mono audio into text
+pub trait SttBackend: Send {
+    fn transcribe(&self, audio_data: &[f32], sample_rate: u32, options: &SttOptions) -> Result<Transcript>;
+
+    /// Transcribes speech that is still in progress. Backends that can should
+    /// split the result into segments so the caller can tell which audio
+    /// has been fully recognized.
+    fn transcribe_partial(&self, audio_data: &[f32], sample_rate: u32, options: &SttOptions) -> Result<Transcript> {
+        self.transcribe(audio_data, sample_rate, options)
+    }
+
+    /// Whether the backend is configured well enough to transcribe
+    fn is_ready(&self) -> bool;
+}
+
+/// Holds the available STT backends and routes transcription to the active one
 pub struct SpeechToText {
-    ctx: Option<WhisperContext>,
-    model_path: Option<PathBuf>,
+    whisper: WhisperBackend,
+    command: Option<CommandSttBackend>,
+    active: SttBackendKind,
+    options: SttOptions,
+    /// Bumped for every background model load so only the latest one is installed
+    load_generation: u64,
 }
 
 impl SpeechToText {
     pub fn new() -> Self {
         Self {
-            ctx: None,
-            model_path: None,
+            whisper: WhisperBackend::new(),
+            command: None,
+            active: SttBackendKind::Whisper,
+            options: SttOptions::default(),
+            load_generation: 0,
         }
     }
 
     pub fn load_model(&mut self, model_path: PathBuf) -> Result<()> {
+        self.whisper.install(LoadedWhisperModel::load(model_path)?);
+        Ok(())
+    }
+
+    /// Registers a background model load and returns its generation
+    pub fn begin_model_load(&mut self) -> u64 {
+        self.load_generation += 1;
+        self.load_generation
+    }
+
+    /// Swaps in a model loaded in the background, unless a newer load was
+    /// started in the meantime. Returns whether the model was installed.
+    pub fn finish_model_load(&mut self, generation: u64, model: LoadedWhisperModel) -> bool {
+        if generation != self.load_generation {
+            log::info!("Discarding superseded Whisper model {:?}", model.path);
+            return false;
+        }
+        self.whisper.install(model);
+        true
+    }
+
+    pub fn whisper_model_path(&self) -> Option<&Path> {
+        self.whisper.model_path.as_deref()
+    }
+
+    pub fn set_command(&mut self, config: CommandSttConfig) -> Result<()> {
+        config.validate()?;
+        self.command = Some(CommandSttBackend::new(config));
+        Ok(())
+    }
+
+    pub fn active_kind(&self) -> SttBackendKind {
+        self.active
+    }
+
+    pub fn set_active(&mut self, kind: SttBackendKind) -> Result<()> {
+        if kind == SttBackendKind::Command && self.command.is_none() {
+            return Err(anyhow!("No STT command configured"));
+        }
+        self.active = kind;
+        Ok(())
+    }
+
+    pub fn options(&self) -> &SttOptions {
+        &self.options
+    }
+
+    pub fn set_options(&mut self, options: SttOptions) -> Result<()> {
+        options.validate()?;
+        self.options = options;
+        Ok(())
+    }
+
+    /// The backend the pipeline should transcribe with
+    pub fn backend(&self) -> &dyn SttBackend {
+        match (self.active, &self.command) {
+            (SttBackendKind::Command, Some(command)) => command,
+            _ => &self.whisper,
+        }
+    }
+}
+
+impl Default for SpeechToText {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A Whisper model loaded without holding the pipeline's STT lock, ready to be
+/// swapped in between utterances
+pub struct LoadedWhisperModel {
+    ctx: WhisperContext,
+    path: PathBuf,
+}
+
+impl LoadedWhisperModel {
+    pub fn load(model_path: PathBuf) -> Result<Self> {
         log::info!("Loading Whisper model from: {:?}", model_path);
 
         if !model_path.exists() {
@@ -28,19 +190,77 @@ impl SpeechToText {
         )
         .map_err(|e| anyhow!("Failed to load Whisper model: {}", e))?;
 
-        self.ctx = Some(ctx);
-        self.model_path = Some(model_path);
-
         log::info!("Whisper model loaded successfully");
-        Ok(())
+        Ok(Self {
+            ctx,
+            path: model_path,
+        })
     }
+}
+
+/// Whisper running in-process through whisper-rs
+pub struct WhisperBackend {
+    ctx: Option<WhisperContext>,
+    model_path: Option<PathBuf>,
+}
+
+impl WhisperBackend {
+    pub fn new() -> Self {
+        Self {
+            ctx: None,
+            model_path: None,
+        }
+    }
+
+    fn install(&mut self, model: LoadedWhisperModel) {
+        log::info!("Using Whisper model {:?}", model.path);
+        self.ctx = Some(model.ctx);
+        self.model_path = Some(model.path);
+    }
+}
 
-    pub fn transcribe(&self, audio_data: &[f32], sample_rate: u32) -> Result<String> {
+impl Default for WhisperBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WhisperBackend {
+    /// Runs Whisper over the audio. `single_segment` keeps the whole utterance
+    /// in one segment, which is faster and what the final transcription wants.
+    fn run(&self, audio_data: &[f32], sample_rate: u32, options: &SttOptions, single_segment: bool) -> Result<Transcript> {
         let ctx = self
             .ctx
             .as_ref()
             .ok_or_else(|| anyhow!("Whisper model not loaded"))?;
 
+        let mut state = ctx.create_state().map_err(|e| anyhow!("Failed to create state: {}", e))?;
+
+        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+
+        // English-only models can neither detect nor translate
+        let language = if ctx.is_multilingual() {
+            options.language.as_str()
+        } else {
+            if options.language != "en" {
+                log::warn!("Model is English-only, ignoring language {}", options.language);
+            }
+            "en"
+        };
+
+        // Configure for real-time transcription
+        params.set_language(Some(language));
+        params.set_translate(options.translate && ctx.is_multilingual());
+        params.set_no_context(true);
+        params.set_single_segment(single_segment);
+        params.set_print_special(false);
+        params.set_print_progress(false);
+        params.set_print_realtime(false);
+        params.set_print_timestamps(false);
+        params.set_suppress_blank(false);  // Don't suppress short utterances
+        params.set_suppress_nst(true);  // But do suppress non-speech noise
+
+        // Copied only now, so the copy is wiped on every way out below.
         // Resample to 16kHz if needed (Whisper expects 16kHz mono)
         // Your mic may run at 96kHz, 48kHz, 44.1kHz, etc - we convert to what Whisper needs
         let mut audio_16k = if sample_rate != 16000 {
@@ -55,81 +275,65 @@ impl SpeechToText {
         const MIN_SAMPLES: usize = 17600;
         if audio_16k.len() < MIN_SAMPLES {
             log::info!("Padding short audio ({} samples) to {} samples for Whisper", audio_16k.len(), MIN_SAMPLES);
-            audio_16k.resize(MIN_SAMPLES, 0.0);
+            // Pad into a new buffer, since growing this one could leave a copy behind
+            let mut padded = vec![0.0; MIN_SAMPLES];
+            padded[..audio_16k.len()].copy_from_slice(&audio_16k);
+            scrub(&mut audio_16k);
+            audio_16k = padded;
         }
 
-        let mut state = ctx.create_state().map_err(|e| anyhow!("Failed to create state: {}", e))?;
-
-        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
-
-        // Configure for real-time, English-only transcription
-        params.set_language(Some("en"));
-        params.set_translate(false);
-        params.set_no_context(true);
-        params.set_single_segment(true);
-        params.set_print_special(false);
-        params.set_print_progress(false);
-        params.set_print_realtime(false);
-        params.set_print_timestamps(false);
-        params.set_suppress_blank(false);  // Don't suppress short utterances
-        params.set_suppress_nst(true);  // But do suppress non-speech noise
-
-        state
-            .full(params, &audio_16k)
-            .map_err(|e| anyhow!("Transcription failed: {}", e))?;
+        let result = state.full(params, &audio_16k);
+        scrub(&mut audio_16k);
+        result.map_err(|e| anyhow!("Transcription failed: {}", e))?;
 
         let num_segments = state.full_n_segments().map_err(|e| anyhow!("Failed to get segments: {}", e))?;
 
         let mut text = String::new();
+        let mut segments = Vec::new();
         for i in 0..num_segments {
             if let Ok(segment) = state.full_get_segment_text(i) {
                 text.push_str(&segment);
                 text.push(' ');
+
+                // Timestamps are in centiseconds
+                let end = state.full_get_segment_t1(i).unwrap_or(0) as f32 / 100.0;
+                segments.push(TranscriptSegment {
+                    text: segment.trim().to_string(),
+                    end,
+                });
             }
         }
 
-        Ok(text.trim().to_string())
-    }
+        let spoken = if language == "auto" {
+            let detected = state
+                .full_lang_id_from_state()
+                .ok()
+                .and_then(whisper_rs::get_lang_str)
+                .map(|l| l.to_string());
+            log::info!("Detected language: {:?}", detected);
+            detected
+        } else {
+            Some(language.to_string())
+        };
 
-    pub fn is_loaded(&self) -> bool {
-        self.ctx.is_some()
+        Ok(Transcript {
+            text: text.trim().to_string(),
+            language: if options.translate { Some("en".to_string()) } else { spoken },
+            segments,
+        })
     }
 }
 
-impl Default for SpeechToText {
-    fn default() -> Self {
-        Self::new()
+impl SttBackend for WhisperBackend {
+    fn transcribe(&self, audio_data: &[f32], sample_rate: u32, options: &SttOptions) -> Result<Transcript> {
+        self.run(audio_data, sample_rate, options, true)
     }
-}
 
-fn resample_audio(input: &[f32], from_rate: u32, to_rate: u32) -> Result<Vec<f32>> {
-    use rubato::{Resampler, SincFixedIn, SincInterpolationParameters, SincInterpolationType, WindowFunction};
-
-    if from_rate == to_rate {
-        return Ok(input.to_vec());
+    fn transcribe_partial(&self, audio_data: &[f32], sample_rate: u32, options: &SttOptions) -> Result<Transcript> {
+        self.run(audio_data, sample_rate, options, false)
     }
 
-    let params = SincInterpolationParameters {
-        sinc_len: 256,
-        f_cutoff: 0.95,
-        interpolation: SincInterpolationType::Linear,
-        oversampling_factor: 256,
-        window: WindowFunction::BlackmanHarris2,
-    };
-
-    let mut resampler = SincFixedIn::<f32>::new(
-        to_rate as f64 / from_rate as f64,
-        2.0,
-        params,
-        input.len(),
-        1,
-    )
-    .map_err(|e| anyhow!("Failed to create resampler: {}", e))?;
-
-    let waves_in = vec![input.to_vec()];
-    let waves_out = resampler
-        .process(&waves_in, None)
-        .map_err(|e| anyhow!("Resampling failed: {}", e))?;
-
-    Ok(waves_out.into_iter().next().unwrap_or_default())
+    fn is_ready(&self) -> bool {
+        self.ctx.is_some()
+    }
 }
@@ -1,153 +1,532 @@
+use crate::audio::resample_audio;
+use crate::command_tts::{CommandBackend, CommandVoiceConfig};
+use crate::disguise::{self, DisguiseSettings};
+use crate::piper::{PiperBackend, PiperVoice};
+use crate::playout::trim_silence;
 use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::PathBuf;
-use std::process::{Command, Stdio};
-use std::io::Write;
 
-#[cfg(windows)]
-use std::os::windows::process::CommandExt;
+/// Which backend a voice belongs to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    Piper,
+    Command,
+}
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct Voice {
     pub id: String,
     pub name: String,
-    pub model_path: PathBuf,
-    pub config_path: PathBuf,
+    pub backend: BackendKind,
+    pub sample_rate: u32,
+    pub language: Option<String>,
+    pub quality: Option<String>,
+    pub num_speakers: u32,
+    /// Selectable speakers for multi-speaker models, empty for single-speaker voices
+    pub speakers: Vec<Speaker>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Speaker {
+    pub id: u32,
+    pub name: String,
+}
+
+impl Voice {
+    pub fn is_multi_speaker(&self) -> bool {
+        self.num_speakers > 1
+    }
+}
+
+/// Piper synthesis parameters controlling how the voice sounds
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Prosody {
+    /// Phoneme duration multiplier; above 1.0 speaks slower
+    pub length_scale: f32,
+    /// Generator noise; higher is more expressive but less stable
+    pub noise_scale: f32,
+    /// Phoneme width noise; higher varies the rhythm more
+    pub noise_w: f32,
+    /// Seconds of silence inserted after each sentence
+    pub sentence_silence: f32,
+}
+
+impl Default for Prosody {
+    fn default() -> Self {
+        // Piper's own defaults
+        Self {
+            length_scale: 1.0,
+            noise_scale: 0.667,
+            noise_w: 0.8,
+            sentence_silence: 0.2,
+        }
+    }
+}
+
+impl Prosody {
+    pub fn validate(&self) -> Result<()> {
+        if !(0.1..=5.0).contains(&self.length_scale) {
+            return Err(anyhow!("length_scale must be between 0.1 and 5.0"));
+        }
+        if !(0.0..=2.0).contains(&self.noise_scale) {
+            return Err(anyhow!("noise_scale must be between 0.0 and 2.0"));
+        }
+        if !(0.0..=2.0).contains(&self.noise_w) {
+            return Err(anyhow!("noise_w must be between 0.0 and 2.0"));
+        }
+        if !(0.0..=5.0).contains(&self.sentence_silence) {
+            return Err(anyhow!("sentence_silence must be between 0.0 and 5.0"));
+        }
+        Ok(())
+    }
+}
+
+/// Speech rendered by a TTS backend. Only `TextToSpeech::synthesize` can
+/// create one and the methods below only ever transform it, which is what
+/// lets the output buffer accept nothing else.
+#[derive(Debug)]
+pub struct SynthesizedAudio {
+    samples: Vec<f32>,
+    sample_rate: u32,
+}
+
+impl SynthesizedAudio {
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Runs the audio through the voice disguise chain
+    pub fn disguised(self, settings: &DisguiseSettings) -> Self {
+        let samples = disguise::apply(&self.samples, self.sample_rate, settings);
+        Self { samples, ..self }
+    }
+
+    pub fn resampled(&self, sample_rate: u32) -> Result<Self> {
+        Ok(Self {
+            samples: resample_audio(&self.samples, self.sample_rate, sample_rate)?,
+            sample_rate,
+        })
+    }
+
+    /// Cuts the silence at either end
+    pub fn trimmed(self) -> Self {
+        let samples = trim_silence(&self.samples).to_vec();
+        Self { samples, ..self }
+    }
+
+    pub fn into_samples(self) -> Vec<f32> {
+        self.samples
+    }
+
+    /// Lets tests stand in for a TTS backend
+    #[cfg(test)]
+    pub fn for_test(samples: Vec<f32>, sample_rate: u32) -> Self {
+        Self { samples, sample_rate }
+    }
+}
+
+/// Per-utterance settings handed to a backend along with the text
+#[derive(Clone, Copy, Debug)]
+pub struct SynthesisOptions {
+    pub speaker_id: Option<u32>,
+    pub prosody: Prosody,
+}
+
+/// A local speech engine that turns text into mono f32 samples
+pub trait TtsBackend: Send {
+    /// Voices this backend can speak with
+    fn list_voices(&self) -> Vec<Voice>;
+
+    /// Sample rate of the audio `synthesize` returns for a voice
+    fn sample_rate(&self, voice_id: &str) -> Option<u32>;
+
+    fn synthesize(&mut self, voice_id: &str, text: &str, options: &SynthesisOptions) -> Result<Vec<f32>>;
+
+    /// Whether the backend is configured well enough to synthesize
+    fn is_available(&self) -> bool {
+        true
+    }
+
+    /// Called when the voice or its options change, so slow backends can load
+    /// ahead of the first utterance
+    fn prepare(&mut self, _voice_id: &str, _options: &SynthesisOptions) -> Result<()> {
+        Ok(())
+    }
 }
 
+/// Front end over the TTS backends. Voices from all backends share one list and
+/// synthesis is routed to the backend that owns the selected voice.
 pub struct TextToSpeech {
-    piper_path: Option<PathBuf>,
-    voices: Vec<Voice>,
+    piper: PiperBackend,
+    command: CommandBackend,
     current_voice: Option<Voice>,
+    current_speaker: Option<u32>,
+    prosody: Prosody,
+    voice_prosody: HashMap<String, Prosody>,
+    /// Takes precedence over the global and per-voice prosody while set
+    prosody_override: Option<Prosody>,
+    /// Switch to a voice for the transcript's language when the selected one
+    /// doesn't speak it
+    match_language: bool,
 }
 
 impl TextToSpeech {
     pub fn new() -> Self {
         Self {
-            piper_path: None,
-            voices: Vec::new(),
+            piper: PiperBackend::new(),
+            command: CommandBackend::new(),
             current_voice: None,
+            current_speaker: None,
+            prosody: Prosody::default(),
+            voice_prosody: HashMap::new(),
+            prosody_override: None,
+            match_language: false,
         }
     }
 
     pub fn set_piper_path(&mut self, path: PathBuf) -> Result<()> {
-        if !path.exists() {
-            return Err(anyhow!("Piper executable not found: {:?}", path));
-        }
-        self.piper_path = Some(path);
+        self.piper.set_piper_path(path)?;
+        self.warm_up();
         Ok(())
     }
 
     pub fn add_voice(&mut self, id: &str, name: &str, model_path: PathBuf, config_path: PathBuf) -> Result<()> {
-        if !model_path.exists() {
-            return Err(anyhow!("Voice model not found: {:?}", model_path));
-        }
-        if !config_path.exists() {
-            return Err(anyhow!("Voice config not found: {:?}", config_path));
+        if self.has_voice(id) {
+            return Err(anyhow!("Voice already exists: {}", id));
         }
+        self.piper.add_voice(PiperVoice::from_files(id, name, model_path, config_path)?);
+        Ok(())
+    }
+
+    /// Replaces the Piper voices, keeping the current voice selected if it is still available
+    pub fn set_voices(&mut self, voices: Vec<PiperVoice>) {
+        self.piper.set_voices(voices);
+        self.refresh_current_voice();
+    }
 
-        self.voices.push(Voice {
-            id: id.to_string(),
-            name: name.to_string(),
-            model_path,
-            config_path,
-        });
+    /// Replaces the command-template voices
+    pub fn set_command_voices(&mut self, configs: Vec<CommandVoiceConfig>) {
+        let configs = configs
+            .into_iter()
+            .filter(|c| match c.validate() {
+                Ok(()) => true,
+                Err(e) => {
+                    log::error!("Skipping command voice {}: {}", c.id, e);
+                    false
+                }
+            })
+            .collect();
+        self.command.set_voices(configs);
+        self.refresh_current_voice();
+    }
 
+    pub fn add_command_voice(&mut self, config: CommandVoiceConfig) -> Result<()> {
+        config.validate()?;
+        if self.has_voice(&config.id) {
+            return Err(anyhow!("Voice already exists: {}", config.id));
+        }
+        self.command.add_voice(config);
         Ok(())
     }
 
-    pub fn list_voices(&self) -> Vec<(String, String)> {
-        self.voices
-            .iter()
-            .map(|v| (v.id.clone(), v.name.clone()))
-            .collect()
+    pub fn remove_command_voice(&mut self, voice_id: &str) -> Result<()> {
+        self.command.remove_voice(voice_id)?;
+        self.refresh_current_voice();
+        Ok(())
     }
 
-    pub fn select_voice(&mut self, voice_id: &str) -> Result<()> {
+    pub fn list_voices(&self) -> Vec<Voice> {
+        let mut voices = self.piper.list_voices();
+        voices.extend(self.command.list_voices());
+        voices
+    }
+
+    fn has_voice(&self, voice_id: &str) -> bool {
+        self.list_voices().iter().any(|v| v.id == voice_id)
+    }
+
+    /// Re-resolves the current voice after a voice list changed
+    fn refresh_current_voice(&mut self) {
+        if let Some(current) = &self.current_voice {
+            match self.list_voices().into_iter().find(|v| v.id == current.id) {
+                Some(voice) => self.current_voice = Some(voice),
+                None => {
+                    log::warn!("Selected voice {} is no longer available", current.id);
+                    self.current_voice = None;
+                    self.current_speaker = None;
+                }
+            }
+        }
+    }
+
+    /// Selects a voice and, for multi-speaker voices, one of its speakers
+    /// (defaulting to the first).
+    pub fn select_voice(&mut self, voice_id: &str, speaker_id: Option<u32>) -> Result<()> {
         let voice = self
-            .voices
-            .iter()
+            .list_voices()
+            .into_iter()
             .find(|v| v.id == voice_id)
-            .cloned()
             .ok_or_else(|| anyhow!("Voice not found: {}", voice_id))?;
 
+        let speaker_id = if voice.is_multi_speaker() {
+            let id = speaker_id.unwrap_or(0);
+            if id >= voice.num_speakers {
+                return Err(anyhow!(
+                    "Speaker {} not found in voice {} ({} speakers)",
+                    id,
+                    voice_id,
+                    voice.num_speakers
+                ));
+            }
+            Some(id)
+        } else {
+            None
+        };
+
         self.current_voice = Some(voice);
+        self.current_speaker = speaker_id;
+        self.warm_up();
         Ok(())
     }
 
-    pub fn synthesize(&self, text: &str) -> Result<Vec<f32>> {
-        let piper_path = self
-            .piper_path
+    /// The selected voice and speaker
+    pub fn selected_voice(&self) -> Option<(String, Option<u32>)> {
+        self.current_voice
             .as_ref()
-            .ok_or_else(|| anyhow!("Piper path not set"))?;
+            .map(|voice| (voice.id.clone(), self.current_speaker))
+    }
+
+    /// Returns the prosody used for a voice: its own override if it has one,
+    /// otherwise the global setting. `None` returns the global setting.
+    pub fn get_prosody(&self, voice_id: Option<&str>) -> Prosody {
+        voice_id
+            .and_then(|id| self.voice_prosody.get(id))
+            .copied()
+            .unwrap_or(self.prosody)
+    }
+
+    /// Sets the global prosody, or a per-voice override when `voice_id` is given
+    pub fn set_prosody(&mut self, voice_id: Option<&str>, prosody: Prosody) -> Result<()> {
+        prosody.validate()?;
+        match voice_id {
+            Some(id) => {
+                self.voice_prosody.insert(id.to_string(), prosody);
+            }
+            None => self.prosody = prosody,
+        }
+        // Restarts the current voice's worker right away if its prosody
+        // changed, so the next utterance doesn't wait for the model to load
+        self.warm_up();
+        Ok(())
+    }
+
+    /// Speaks every voice with `prosody` until cleared with `None`, without
+    /// touching the saved global or per-voice settings
+    pub fn set_prosody_override(&mut self, prosody: Option<Prosody>) -> Result<()> {
+        if let Some(prosody) = &prosody {
+            prosody.validate()?;
+        }
+        self.prosody_override = prosody;
+        self.warm_up();
+        Ok(())
+    }
+
+    pub fn match_language(&self) -> bool {
+        self.match_language
+    }
+
+    pub fn set_match_language(&mut self, enabled: bool) {
+        self.match_language = enabled;
+    }
+
+    /// Removes a voice's override so it follows the global prosody again
+    pub fn clear_voice_prosody(&mut self, voice_id: &str) {
+        self.voice_prosody.remove(voice_id);
+        self.warm_up();
+    }
+
+    fn backend(&self, kind: BackendKind) -> &dyn TtsBackend {
+        match kind {
+            BackendKind::Piper => &self.piper,
+            BackendKind::Command => &self.command,
+        }
+    }
 
+    fn backend_mut(&mut self, kind: BackendKind) -> &mut dyn TtsBackend {
+        match kind {
+            BackendKind::Piper => &mut self.piper,
+            BackendKind::Command => &mut self.command,
+        }
+    }
+
+    /// The prosody a voice is spoken with, including any override
+    fn prosody_for(&self, voice_id: &str) -> Prosody {
+        self.prosody_override
+            .unwrap_or_else(|| self.get_prosody(Some(voice_id)))
+    }
+
+    /// The selected voice and the options to synthesize it with
+    fn current(&self) -> Result<(Voice, SynthesisOptions)> {
         let voice = self
             .current_voice
-            .as_ref()
+            .clone()
             .ok_or_else(|| anyhow!("No voice selected"))?;
+        let options = SynthesisOptions {
+            speaker_id: self.current_speaker,
+            prosody: self.prosody_for(&voice.id),
+        };
+        Ok((voice, options))
+    }
 
-        if text.trim().is_empty() {
-            return Ok(Vec::new());
+    /// Lets the backend load the voice ahead of the first utterance so the
+    /// model load is not paid on the first sentence.
+    fn warm_up(&mut self) {
+        if !self.is_ready() {
+            return;
         }
+        if let Ok((voice, options)) = self.current() {
+            if let Err(e) = self.backend_mut(voice.backend).prepare(&voice.id, &options) {
+                log::error!("Failed to prepare voice {}: {}", voice.id, e);
+            }
+        }
+    }
 
-        log::info!("Synthesizing: {}", text);
-
-        // Run piper and capture raw audio output
-        let mut cmd = Command::new(piper_path);
-        cmd.args([
-                "--model", voice.model_path.to_str().unwrap(),
-                "--config", voice.config_path.to_str().unwrap(),
-                "--output-raw",
-            ])
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped());
+    /// Picks the voice for an utterance in `language`. With language matching
+    /// off this is always the selected voice. Otherwise it is the selected voice
+    /// if it speaks that language (or the language is unknown), else the first
+    /// available voice that does, preferring the selected voice's backend.
+    fn voice_for_language(&self, language: Option<&str>) -> Result<(Voice, SynthesisOptions)> {
+        let (current, options) = self.current()?;
+        if !self.match_language {
+            return Ok((current, options));
+        }
 
-        // Hide console window on Windows
-        #[cfg(windows)]
-        cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
+        let wanted = match language {
+            Some(language) => primary_language(language),
+            None => return Ok((current, options)),
+        };
+        let speaks = |voice: &Voice| {
+            voice.language.as_deref().map(primary_language).as_deref() == Some(wanted.as_str())
+        };
+        if current.language.is_none() || speaks(&current) {
+            return Ok((current, options));
+        }
 
-        let mut child = cmd.spawn()
-            .map_err(|e| anyhow!("Failed to spawn piper: {}", e))?;
+        let mut candidates: Vec<Voice> = self
+            .list_voices()
+            .into_iter()
+            .filter(|v| speaks(v) && self.backend(v.backend).is_available())
+            .collect();
+        candidates.sort_by_key(|v| v.backend != current.backend);
 
-        // Write text to stdin
-        if let Some(mut stdin) = child.stdin.take() {
-            stdin.write_all(text.as_bytes())?;
+        match candidates.into_iter().next() {
+            Some(voice) => {
+                log::info!("Using voice {} for language {}", voice.id, wanted);
+                // Keep the user's speaker if the other voice has one with that id
+                let speaker_id = voice
+                    .is_multi_speaker()
+                    .then(|| options.speaker_id.filter(|id| *id < voice.num_speakers).unwrap_or(0));
+                let options = SynthesisOptions {
+                    speaker_id,
+                    prosody: self.prosody_for(&voice.id),
+                };
+                Ok((voice, options))
+            }
+            None => Ok((current, options)),
         }
+    }
 
-        let output = child.wait_with_output()?;
+    /// Synthesizes text with the selected voice, switching to a voice for
+    /// `language` if matching is on and the selected one doesn't speak it.
+    pub fn synthesize(&mut self, text: &str, language: Option<&str>) -> Result<SynthesizedAudio> {
+        let (voice, options) = self.voice_for_language(language)?;
+        let sample_rate = self
+            .backend(voice.backend)
+            .sample_rate(&voice.id)
+            .unwrap_or(voice.sample_rate);
 
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow!("Piper failed: {}", stderr));
+        if text.trim().is_empty() {
+            return Ok(SynthesizedAudio {
+                samples: Vec::new(),
+                sample_rate,
+            });
         }
 
-        // Convert raw PCM (16-bit signed, 22050 Hz) to f32
-        let samples: Vec<f32> = output
-            .stdout
-            .chunks_exact(2)
-            .map(|chunk| {
-                let sample = i16::from_le_bytes([chunk[0], chunk[1]]);
-                sample as f32 / 32768.0
-            })
-            .collect();
+        log::info!("Synthesizing with {}: {}", voice.id, text);
 
+        let samples = self.backend_mut(voice.backend).synthesize(&voice.id, text, &options)?;
         log::info!("Synthesized {} samples", samples.len());
 
-        Ok(samples)
-    }
-
-    pub fn get_sample_rate(&self) -> u32 {
-        // Piper outputs at 22050 Hz by default
-        22050
+        Ok(SynthesizedAudio { samples, sample_rate })
     }
 
     pub fn is_ready(&self) -> bool {
-        self.piper_path.is_some() && self.current_voice.is_some()
+        self.current_voice
+            .as_ref()
+            .map(|voice| self.backend(voice.backend).is_available())
+            .unwrap_or(false)
     }
 }
 
+/// Reduces a language tag like "en_US", "en-gb" or "en" to its primary subtag
+fn primary_language(tag: &str) -> String {
+    tag.split(['_', '-'])
+        .next()
+        .unwrap_or(tag)
+        .to_lowercase()
+}
+
 impl Default for TextToSpeech {
     fn default() -> Self {
         Self::new()
     }
 }
+
+/// Sentences longer than this are also split at clause punctuation
+const MAX_CHUNK_CHARS: usize = 120;
+/// Chunks shorter than this are joined to the next one so synthesis doesn't sound choppy
+const MIN_CHUNK_CHARS: usize = 12;
+
+/// Splits text into sentences, and long sentences into clauses, so each piece
+/// can be synthesized and played while the next is still rendering
+pub fn split_sentences(text: &str) -> Vec<String> {
+    let mut chunks: Vec<String> = Vec::new();
+    let mut current = String::new();
+
+    for word in text.split_whitespace() {
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(word);
+
+        let sentence_end = word.ends_with(['.', '!', '?']);
+        let clause_end = word.ends_with([',', ';', ':']) && current.len() >= MAX_CHUNK_CHARS;
+        if (sentence_end || clause_end) && current.len() >= MIN_CHUNK_CHARS {
+            chunks.push(std::mem::take(&mut current));
+        }
+    }
+
+    if !current.is_empty() {
+        // Don't leave a dangling fragment on its own
+        match chunks.last_mut() {
+            Some(last) if current.len() < MIN_CHUNK_CHARS => {
+                last.push(' ');
+                last.push_str(&current);
+            }
+            _ => chunks.push(current),
+        }
+    }
+
+    chunks
+}
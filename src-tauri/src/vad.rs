@@ -0,0 +1,409 @@
+#[cfg(feature = "silero-vad")]
+use crate::silero::SileroWorker;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Length of one analysis frame
+const FRAME_MS: u32 = 10;
+/// How quickly the noise floor drops back when the room gets quieter
+const FLOOR_FALL_MS: f32 = 100.0;
+/// The floor rises this many times slower during speech, so a noise source
+/// that starts mid-utterance is eventually absorbed instead of recording forever
+const SPEECH_FLOOR_SLOWDOWN: f32 = 4.0;
+/// Longest pre-roll or post-roll allowed, in ms
+pub const MAX_ROLL_MS: u64 = 1000;
+
+/// Which detector decides what counts as speech
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VadKind {
+    /// Signal level against the noise floor
+    #[default]
+    Energy,
+    /// The Silero neural model, which ignores most clicks and music
+    Silero,
+}
+
+/// Tuning for the adaptive voice activity detector
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct VadSettings {
+    /// Detector in use. Falls back to energy detection if Silero isn't available.
+    #[serde(default)]
+    pub detector: VadKind,
+    /// Speech probability above which Silero starts speech
+    #[serde(default = "default_speech_probability")]
+    pub speech_probability: f32,
+    /// How far above the noise floor the level must rise to start speech, in dB
+    pub open_db: f32,
+    /// How far above the noise floor the level must stay to continue speech, in dB
+    pub close_db: f32,
+    /// Lowest RMS that can count as speech, however quiet the room is
+    pub min_threshold: f32,
+    /// Consecutive 10 ms frames above the open threshold needed to start speech
+    pub attack_frames: u32,
+    /// Consecutive 10 ms frames below the close threshold before speech ends
+    pub hangover_frames: u32,
+    /// Time constant for the noise floor following louder background noise, in ms
+    pub floor_rise_ms: u32,
+}
+
+fn default_speech_probability() -> f32 {
+    0.5
+}
+
+impl Default for VadSettings {
+    fn default() -> Self {
+        Self {
+            detector: VadKind::default(),
+            speech_probability: default_speech_probability(),
+            open_db: 12.0,
+            close_db: 6.0,
+            min_threshold: 0.003,
+            attack_frames: 3,
+            hangover_frames: 15,
+            floor_rise_ms: 3000,
+        }
+    }
+}
+
+impl VadSettings {
+    pub fn validate(&self) -> Result<()> {
+        if !(0.0..=40.0).contains(&self.close_db) || !(0.0..=40.0).contains(&self.open_db) {
+            return Err(anyhow!("VAD thresholds must be between 0 and 40 dB"));
+        }
+        if self.close_db > self.open_db {
+            return Err(anyhow!("VAD close threshold must not be above the open threshold"));
+        }
+        if !(self.min_threshold > 0.0 && self.min_threshold < 1.0) {
+            return Err(anyhow!("VAD minimum threshold must be between 0 and 1"));
+        }
+        if self.attack_frames == 0 {
+            return Err(anyhow!("VAD needs at least one attack frame"));
+        }
+        if self.floor_rise_ms < 100 {
+            return Err(anyhow!("VAD noise floor rise time must be at least 100 ms"));
+        }
+        if !(self.speech_probability > 0.0 && self.speech_probability < 1.0) {
+            return Err(anyhow!("VAD speech probability must be between 0 and 1"));
+        }
+        Ok(())
+    }
+
+    /// Time above the open threshold needed to start speech
+    pub fn attack_ms(&self) -> u32 {
+        self.attack_frames * FRAME_MS
+    }
+
+    /// Time below the close threshold before speech ends
+    pub fn hangover_ms(&self) -> u32 {
+        self.hangover_frames * FRAME_MS
+    }
+}
+
+/// How speech is cut into utterances. All times are in ms.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VadConfig {
+    /// Pause that ends an utterance
+    pub silence_duration_ms: u64,
+    /// Utterances shorter than this wait for more speech instead of being sent
+    pub min_speech_ms: u64,
+    /// Audio from before speech was detected, to capture word beginnings
+    pub pre_roll_ms: u64,
+    /// Audio kept after speech ends, to capture word endings
+    pub post_roll_ms: u64,
+    /// Utterances longer than this are cut and sent on in pieces
+    pub max_segment_ms: u64,
+}
+
+impl Default for VadConfig {
+    fn default() -> Self {
+        Self {
+            silence_duration_ms: 700,
+            min_speech_ms: 300,
+            pre_roll_ms: 250,
+            post_roll_ms: 200,
+            max_segment_ms: 15000,
+        }
+    }
+}
+
+impl VadConfig {
+    pub fn validate(&self) -> Result<()> {
+        if !(100..=5000).contains(&self.silence_duration_ms) {
+            return Err(anyhow!("Silence duration must be between 100 and 5000 ms"));
+        }
+        if self.min_speech_ms > 2000 {
+            return Err(anyhow!("Minimum speech duration must be at most 2000 ms"));
+        }
+        if self.pre_roll_ms > MAX_ROLL_MS || self.post_roll_ms > MAX_ROLL_MS {
+            return Err(anyhow!("Pre-roll and post-roll must be at most {} ms", MAX_ROLL_MS));
+        }
+        if self.post_roll_ms > self.silence_duration_ms {
+            return Err(anyhow!("Post-roll must not be longer than the silence duration"));
+        }
+        // Whisper handles at most 30 s at a time
+        if !(2000..=30000).contains(&self.max_segment_ms) {
+            return Err(anyhow!("Maximum segment length must be between 2000 and 30000 ms"));
+        }
+        Ok(())
+    }
+}
+
+/// Detectors this build can run
+pub fn available_detectors() -> Vec<VadKind> {
+    if cfg!(feature = "silero-vad") {
+        vec![VadKind::Energy, VadKind::Silero]
+    } else {
+        vec![VadKind::Energy]
+    }
+}
+
+/// Voice activity detection against a tracked noise floor.
+///
+/// The floor follows the quietest recent level: it drops quickly and rises
+/// slowly, and even more slowly during speech so talking barely raises it.
+/// Speech starts once the level clears the open threshold for `attack_frames`
+/// and ends once it stays under the lower close threshold for `hangover_frames`.
+///
+/// When Silero is selected and loaded it decides what is speech instead, from
+/// its own thread, while the noise floor keeps being tracked.
+pub struct VoiceActivityDetector {
+    settings: VadSettings,
+    frame_len: usize,
+    noise_floor: Option<f32>,
+    active: bool,
+    /// Time the level has been on the other side of the threshold
+    run_ms: u32,
+    #[cfg(feature = "silero-vad")]
+    sample_rate: u32,
+    #[cfg(feature = "silero-vad")]
+    silero: Option<SileroWorker>,
+}
+
+impl VoiceActivityDetector {
+    pub fn new(settings: VadSettings, sample_rate: u32) -> Self {
+        Self {
+            settings,
+            frame_len: (sample_rate * FRAME_MS / 1000).max(1) as usize,
+            noise_floor: None,
+            active: false,
+            run_ms: 0,
+            #[cfg(feature = "silero-vad")]
+            sample_rate,
+            #[cfg(feature = "silero-vad")]
+            silero: None,
+        }
+    }
+
+    /// Loads the Silero model so it can be selected, live, in the settings
+    pub fn load_silero(&mut self, model_path: &Path) {
+        #[cfg(feature = "silero-vad")]
+        match SileroWorker::load(model_path, self.sample_rate) {
+            Ok(silero) => self.silero = Some(silero),
+            Err(e) => log::error!("Silero VAD unavailable, using energy detection: {}", e),
+        }
+
+        #[cfg(not(feature = "silero-vad"))]
+        log::warn!("Built without the silero-vad feature, ignoring {:?}", model_path);
+    }
+
+    pub fn set_settings(&mut self, settings: VadSettings) {
+        self.settings = settings;
+    }
+
+    /// Feeds a block of mono samples and returns whether speech is active
+    pub fn process(&mut self, samples: &[f32]) -> bool {
+        for frame in samples.chunks(self.frame_len) {
+            let rms = (frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32).sqrt();
+            self.process_frame(rms);
+        }
+
+        #[cfg(feature = "silero-vad")]
+        if self.settings.detector == VadKind::Silero {
+            if let Some(silero) = self.silero.as_ref() {
+                match silero.process(samples, &self.settings) {
+                    Ok(active) => return active,
+                    Err(e) => {
+                        log::error!("Silero VAD failed, falling back to energy detection: {}", e);
+                        self.silero = None;
+                    }
+                }
+            }
+        }
+
+        self.active
+    }
+
+    pub fn noise_floor(&self) -> f32 {
+        self.noise_floor.unwrap_or(0.0)
+    }
+
+    /// Level speech has to exceed to start
+    pub fn open_threshold(&self) -> f32 {
+        self.threshold(self.settings.open_db)
+    }
+
+    /// Level speech has to stay above to continue
+    pub fn close_threshold(&self) -> f32 {
+        self.threshold(self.settings.close_db)
+    }
+
+    fn threshold(&self, db: f32) -> f32 {
+        (self.noise_floor() * 10f32.powf(db / 20.0)).max(self.settings.min_threshold)
+    }
+
+    fn process_frame(&mut self, rms: f32) {
+        if self.active {
+            self.track_floor(rms, SPEECH_FLOOR_SLOWDOWN);
+            if rms < self.close_threshold() {
+                self.run_ms += FRAME_MS;
+                if self.run_ms >= self.settings.hangover_ms() {
+                    self.active = false;
+                    self.run_ms = 0;
+                }
+            } else {
+                self.run_ms = 0;
+            }
+        } else {
+            self.track_floor(rms, 1.0);
+            if rms > self.open_threshold() {
+                self.run_ms += FRAME_MS;
+                if self.run_ms >= self.settings.attack_ms() {
+                    self.active = true;
+                    self.run_ms = 0;
+                }
+            } else {
+                self.run_ms = 0;
+            }
+        }
+    }
+
+    fn track_floor(&mut self, rms: f32, slowdown: f32) {
+        let floor = match self.noise_floor {
+            Some(floor) => floor,
+            None => {
+                self.noise_floor = Some(rms);
+                return;
+            }
+        };
+
+        let time_constant = if rms < floor {
+            FLOOR_FALL_MS
+        } else {
+            self.settings.floor_rise_ms as f32 * slowdown
+        };
+        let alpha = 1.0 - (-(FRAME_MS as f32) / time_constant).exp();
+        self.noise_floor = Some(floor + alpha * (rms - floor));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 16000;
+    const FRAME: usize = (RATE * FRAME_MS / 1000) as usize;
+
+    /// One 10 ms frame with the given RMS
+    fn frame(rms: f32) -> Vec<f32> {
+        (0..FRAME).map(|i| if i % 2 == 0 { rms } else { -rms }).collect()
+    }
+
+    /// A detector whose thresholds depend on the noise floor alone
+    fn detector() -> VoiceActivityDetector {
+        let settings = VadSettings {
+            min_threshold: 1e-6,
+            ..Default::default()
+        };
+        VoiceActivityDetector::new(settings, RATE)
+    }
+
+    fn feed(vad: &mut VoiceActivityDetector, rms: f32, frames: usize) {
+        for _ in 0..frames {
+            vad.process(&frame(rms));
+        }
+    }
+
+    #[test]
+    fn opens_above_open_threshold_and_closes_below_close_threshold() {
+        let mut vad = detector();
+        let settings = VadSettings::default();
+        feed(&mut vad, 0.001, 200);
+
+        // Rise to 10x the room level and fall back again, 1 s each way
+        let ramp: Vec<f32> = (0..=100).map(|i| 0.001 * 10f32.powf(i as f32 / 100.0)).collect();
+        let levels: Vec<f32> = ramp.iter().chain(ramp.iter().rev()).copied().chain([0.001; 100]).collect();
+
+        // Per frame: level, open and close thresholds it was compared with, state after
+        let mut history = Vec::new();
+        for &level in &levels {
+            let active = vad.process(&frame(level));
+            history.push((level, vad.open_threshold(), vad.close_threshold(), active));
+        }
+
+        let opened = history.iter().position(|h| h.3).expect("speech never started");
+        let closed = opened + history[opened..].iter().position(|h| !h.3).expect("speech never ended");
+        let attack = settings.attack_frames as usize;
+        let hangover = settings.hangover_frames as usize;
+
+        assert!(history[opened + 1 - attack..=opened].iter().all(|(level, open, _, _)| level > open));
+        // The frames just before opening were between the thresholds, and didn't open it
+        assert!(history[..opened + 1 - attack].iter().any(|(level, open, close, _)| level > close && level <= open));
+        // Speech only ended after a full hangover below the close threshold,
+        // not when the level first dropped under the open threshold
+        assert!(history[closed + 1 - hangover..=closed].iter().all(|(level, _, close, _)| level < close));
+        assert!(history[opened..closed + 1 - hangover].iter().any(|(level, open, close, _)| level >= close && level <= open));
+        assert!(history[closed..].iter().all(|h| !h.3));
+    }
+
+    #[test]
+    fn noise_floor_tracks_steady_noise() {
+        let mut vad = detector();
+        feed(&mut vad, 0.001, 100);
+        assert!((vad.noise_floor() / 0.001 - 1.0).abs() < 0.01);
+
+        // A fan starts: at first it sounds like speech, then it becomes the room
+        feed(&mut vad, 0.02, 6000);
+        assert!((vad.noise_floor() / 0.02 - 1.0).abs() < 0.05);
+        assert!(!vad.process(&frame(0.02)));
+
+        // And once it stops, the floor drops back quickly
+        feed(&mut vad, 0.001, 100);
+        assert!((vad.noise_floor() / 0.001 - 1.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn config_validation_enforces_ranges() {
+        let valid = VadConfig {
+            silence_duration_ms: 5000,
+            ..Default::default()
+        };
+        assert!(valid.validate().is_ok());
+
+        let rejected = [
+            VadConfig { silence_duration_ms: 99, post_roll_ms: 0, ..valid },
+            VadConfig { silence_duration_ms: 5001, ..valid },
+            VadConfig { min_speech_ms: 2001, ..valid },
+            VadConfig { pre_roll_ms: 1001, ..valid },
+            VadConfig { post_roll_ms: 1001, ..valid },
+            VadConfig { max_segment_ms: 1999, ..valid },
+            VadConfig { max_segment_ms: 30001, ..valid },
+        ];
+        for config in rejected {
+            assert!(config.validate().is_err(), "{:?} was accepted", config);
+        }
+
+        let bounds = [
+            VadConfig { silence_duration_ms: 100, post_roll_ms: 100, ..valid },
+            VadConfig { min_speech_ms: 2000, ..valid },
+            VadConfig { pre_roll_ms: 1000, post_roll_ms: 1000, ..valid },
+            VadConfig { max_segment_ms: 2000, ..valid },
+            VadConfig { max_segment_ms: 30000, ..valid },
+        ];
+        for config in bounds {
+            assert!(config.validate().is_ok(), "{:?} was rejected", config);
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use crate::piper::{PiperVoice, VoiceConfig};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Discovers Piper voices by scanning directories for `*.onnx` models that
+/// have a matching `*.onnx.json` config next to them.
+pub struct VoiceRegistry {
+    dirs: Vec<PathBuf>,
+}
+
+impl VoiceRegistry {
+    pub fn new(dirs: Vec<PathBuf>) -> Self {
+        let mut registry = Self { dirs: Vec::new() };
+        for dir in dirs {
+            registry.add_dir(dir);
+        }
+        registry
+    }
+
+    pub fn add_dir(&mut self, dir: PathBuf) {
+        if !self.dirs.contains(&dir) {
+            self.dirs.push(dir);
+        }
+    }
+
+    /// Scans all directories. When the same voice exists in several
+    /// directories, the one in the earliest directory wins.
+    pub fn scan(&self) -> Vec<PiperVoice> {
+        let mut voices: Vec<PiperVoice> = Vec::new();
+
+        for dir in &self.dirs {
+            let entries = match fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+
+            let mut models: Vec<PathBuf> = entries
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| p.extension().map(|ext| ext == "onnx").unwrap_or(false))
+                .collect();
+            models.sort();
+
+            for model_path in models {
+                let id = match model_path.file_stem().and_then(|s| s.to_str()) {
+                    Some(id) => id.to_string(),
+                    None => continue,
+                };
+                if voices.iter().any(|v| v.voice.id == id) {
+                    continue;
+                }
+
+                let config_path = model_path.with_extension("onnx.json");
+                if !config_path.exists() {
+                    log::warn!("Skipping voice {:?}: no {:?}", model_path, config_path);
+                    continue;
+                }
+
+                let name = display_name(&id, &config_path);
+                match PiperVoice::from_files(&id, &name, model_path, config_path) {
+                    Ok(voice) => {
+                        log::info!("Found voice: {} ({})", voice.voice.name, voice.voice.id);
+                        voices.push(voice);
+                    }
+                    Err(e) => log::error!("Failed to load voice {}: {}", id, e),
+                }
+            }
+        }
+
+        voices.sort_by(|a, b| a.voice.name.cmp(&b.voice.name));
+        log::info!("Discovered {} voices in {:?}", voices.len(), self.dirs);
+        voices
+    }
+}
+
+/// Builds a display name like "Lessac (en_US, medium)" from the voice config,
+/// falling back to the file name
+fn display_name(id: &str, config_path: &Path) -> String {
+    let config = match VoiceConfig::load(config_path) {
+        Ok(config) => config,
+        Err(_) => return id.to_string(),
+    };
+
+    let dataset = match config.dataset {
+        Some(dataset) if !dataset.is_empty() => dataset,
+        _ => return id.to_string(),
+    };
+
+    let mut name: String = dataset
+        .split('_')
+        .filter(|w| !w.is_empty())
+        .map(|w| {
+            let mut chars = w.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect::<String>(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let details: Vec<String> = [config.language.and_then(|l| l.code), config.audio.quality]
+        .into_iter()
+        .flatten()
+        .collect();
+    if !details.is_empty() {
+        name.push_str(&format!(" ({})", details.join(", ")));
+    }
+
+    name
+}
@@ -2,11 +2,30 @@ import { useState, useEffect, useRef } from "react";
 import { invoke } from "@tauri-apps/api/core";
 import { listen } from "@tauri-apps/api/event";
 
+interface Speaker {
+  id: number;
+  name: string;
+}
+
+interface Voice {
+  id: string;
+  name: string;
+  backend: "piper" | "command";
+  sample_rate: number;
+  language: string | null;
+  quality: string | null;
+  num_speakers: number;
+  speakers: Speaker[];
+}
+
 interface Settings {
   input_device: string | null;
   output_device: string | null;
   voice_id: string | null;
+  speaker_id: number | null;
   silence_duration_ms: number;
+  // Fields managed by the backend are passed through untouched
+  [key: string]: unknown;
 }
 
 function App() {
@@ -17,8 +36,9 @@ function App() {
   const [selectedInput, setSelectedInput] = useState<string>("");
   const [selectedOutput, setSelectedOutput] = useState<string>("");
   const [silenceDuration, setSilenceDuration] = useState(700);
-  const [voices, setVoices] = useState<[string, string][]>([]);
+  const [voices, setVoices] = useState<Voice[]>([]);
   const [selectedVoice, setSelectedVoice] = useState<string>("");
+  const [selectedSpeaker, setSelectedSpeaker] = useState<number | null>(null);
   const settingsLoaded = useRef(false);
 
   useEffect(() => {
@@ -61,10 +81,19 @@ function App() {
   }
 
   async function saveCurrentSettings(overrides: Partial<Settings> = {}) {
+    // Start from what is on disk so settings persisted by the backend are kept
+    let stored: Settings | null = null;
+    try {
+      stored = await invoke<Settings>("load_settings");
+    } catch (error) {
+      console.error("Failed to load settings:", error);
+    }
     const settings: Settings = {
+      ...stored,
       input_device: overrides.input_device !== undefined ? overrides.input_device : selectedInput || null,
       output_device: overrides.output_device !== undefined ? overrides.output_device : selectedOutput || null,
       voice_id: overrides.voice_id !== undefined ? overrides.voice_id : selectedVoice || null,
+      speaker_id: overrides.speaker_id !== undefined ? overrides.speaker_id : selectedSpeaker,
       silence_duration_ms: overrides.silence_duration_ms !== undefined ? overrides.silence_duration_ms : silenceDuration,
     };
     try {
@@ -76,16 +105,20 @@ function App() {
 
   async function loadVoices(savedSettings: Settings | null) {
     try {
-      const voiceList = await invoke<[string, string][]>("list_voices");
+      const voiceList = await invoke<Voice[]>("list_voices");
       setVoices(voiceList);
 
       // Use saved voice if available and exists in voice list, otherwise use first voice
-      const savedVoiceExists = savedSettings?.voice_id && voiceList.some(([id]) => id === savedSettings.voice_id);
-      const voiceToUse = savedVoiceExists ? savedSettings!.voice_id! : voiceList[0]?.[0];
+      const savedVoiceExists = savedSettings?.voice_id && voiceList.some((v) => v.id === savedSettings.voice_id);
+      const voiceToUse = savedVoiceExists ? savedSettings!.voice_id! : voiceList[0]?.id;
 
       if (voiceToUse) {
+        const voice = voiceList.find((v) => v.id === voiceToUse);
+        const savedSpeaker = savedVoiceExists ? savedSettings!.speaker_id : null;
+        const speakerToUse = voice && voice.speakers.length > 0 ? savedSpeaker ?? voice.speakers[0].id : null;
         setSelectedVoice(voiceToUse);
-        await invoke("select_voice", { voiceId: voiceToUse });
+        setSelectedSpeaker(speakerToUse);
+        await invoke("select_voice", { voiceId: voiceToUse, speakerId: speakerToUse });
       }
     } catch (error) {
       console.error("Failed to load voices:", error);
@@ -94,9 +127,22 @@ function App() {
 
   async function handleVoiceChange(voiceId: string) {
     try {
-      await invoke("select_voice", { voiceId });
+      const voice = voices.find((v) => v.id === voiceId);
+      const speakerId = voice && voice.speakers.length > 0 ? voice.speakers[0].id : null;
+      await invoke("select_voice", { voiceId, speakerId });
       setSelectedVoice(voiceId);
-      await saveCurrentSettings({ voice_id: voiceId });
+      setSelectedSpeaker(speakerId);
+      await saveCurrentSettings({ voice_id: voiceId, speaker_id: speakerId });
+    } catch (error) {
+      setStatus(`Error: ${error}`);
+    }
+  }
+
+  async function handleSpeakerChange(speakerId: number) {
+    try {
+      await invoke("select_voice", { voiceId: selectedVoice, speakerId });
+      setSelectedSpeaker(speakerId);
+      await saveCurrentSettings({ speaker_id: speakerId });
     } catch (error) {
       setStatus(`Error: ${error}`);
     }
@@ -187,6 +233,8 @@ function App() {
     }
   }
 
+  const currentSpeakers = voices.find((v) => v.id === selectedVoice)?.speakers ?? [];
+
   return (
     <main className="container">
       <h1>Parrot</h1>
@@ -233,15 +281,32 @@ function App() {
             {voices.length === 0 ? (
               <option value="">No voices available</option>
             ) : (
-              voices.map(([id, name]) => (
-                <option key={id} value={id}>
-                  {name}
+              voices.map((voice) => (
+                <option key={voice.id} value={voice.id}>
+                  {voice.name}
                 </option>
               ))
             )}
           </select>
         </div>
 
+        {currentSpeakers.length > 0 && (
+          <div className="device-select">
+            <label>Speaker</label>
+            <select
+              value={selectedSpeaker ?? ""}
+              onChange={(e) => handleSpeakerChange(Number(e.target.value))}
+              disabled={isActive}
+            >
+              {currentSpeakers.map((speaker) => (
+                <option key={speaker.id} value={speaker.id}>
+                  {speaker.name}
+                </option>
+              ))}
+            </select>
+          </div>
+        )}
+
         <div className="slider-control">
           <label>
             Silence Detection: {silenceDuration}ms